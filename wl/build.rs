@@ -0,0 +1,15 @@
+use std::path::Path;
+use wayland_scanner::{generate_code, Side};
+
+/// Protocols newer than the wayland-protocols release we build against
+static PROTOCOLS: &[&str] = &["fractional-scale-v1"];
+
+fn main() {
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    for name in PROTOCOLS {
+        let xml = format!("protocols/{}.xml", name);
+        println!("cargo:rerun-if-changed={}", xml);
+        let api = Path::new(&out_dir).join(format!("{}_client_api.rs", name));
+        generate_code(&xml, api, Side::Client);
+    }
+}
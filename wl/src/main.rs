@@ -1,23 +1,62 @@
 use anyhow::{anyhow, Context, Result};
-use std::cmp::max;
-use std::io::BufRead;
+use std::io::{BufRead, Read};
 use wayland_client::protocol::{
+    wl_callback,
     wl_compositor::WlCompositor,
-    wl_pointer,
+    wl_keyboard::{self, WlKeyboard},
+    wl_output::{self, WlOutput},
+    wl_pointer::{self, WlPointer},
     wl_seat::{self, WlSeat},
     wl_shm::{self, WlShm},
-    wl_surface::WlSurface,
+    wl_surface::{self, WlSurface},
+    wl_touch::{self, WlTouch},
 };
 use wayland_client::EventQueue;
-use wayland_client::{self, Display, Filter, GlobalManager, Main};
+use wayland_client::{self, Display, Filter, GlobalError, GlobalEvent, GlobalManager, Main};
+use wayland_client::{Interface, Proxy};
+use wayland_protocols::unstable::xdg_output::v1::client::{
+    zxdg_output_manager_v1::ZxdgOutputManagerV1 as XdgOutputManager, zxdg_output_v1,
+};
+use wayland_protocols::viewporter::client::{
+    wp_viewport::WpViewport as Viewport, wp_viewporter::WpViewporter as Viewporter,
+};
 use wayland_protocols::wlr::unstable::layer_shell::v1::client::{
-    zwlr_layer_shell_v1::{self as layer_shell, Layer, ZwlrLayerShellV1 as LayerShell},
+    zwlr_layer_shell_v1::{Layer, ZwlrLayerShellV1 as LayerShell},
     zwlr_layer_surface_v1::{self as layer_surface, ZwlrLayerSurfaceV1 as LayerSurface},
 };
 use wayland_protocols::xdg_shell::client::{
+    xdg_surface::{self, XdgSurface},
+    xdg_toplevel,
     xdg_wm_base::{self, XdgWmBase},
 };
 
+/// Client bindings build.rs generates for protocols newer than wayland-protocols 0.28
+mod protocols {
+    pub mod fractional_scale_v1 {
+        #![allow(dead_code, non_camel_case_types, unused_unsafe, unused_variables)]
+        #![allow(non_upper_case_globals, non_snake_case, unused_imports)]
+        #![allow(missing_docs, static_mut_refs, clippy::all)]
+
+        pub mod client {
+            pub(crate) use wayland_client::protocol::wl_surface;
+            pub(crate) use wayland_client::sys;
+            pub(crate) use wayland_client::{AnonymousObject, Attached, Main, Proxy, ProxyMap};
+            pub(crate) use wayland_commons::map::{Object, ObjectMetadata};
+            pub(crate) use wayland_commons::smallvec;
+            pub(crate) use wayland_commons::wire::{Argument, ArgumentType, Message, MessageDesc};
+            pub(crate) use wayland_commons::{Interface, MessageGroup};
+            include!(concat!(
+                env!("OUT_DIR"),
+                "/fractional-scale-v1_client_api.rs"
+            ));
+        }
+    }
+}
+use protocols::fractional_scale_v1::client::{
+    wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1 as FractionalScaleManager,
+    wp_fractional_scale_v1 as fractional_scale,
+};
+
 macro_rules! filter {
     ($self:ident, $data:ident, $($p:pat => $body:expr),*) => {
         $self.assign(
@@ -33,47 +72,354 @@ macro_rules! filter {
 }
 
 mod conf {
-    use super::Font;
+    use super::{layer_surface, Font, Format, Layer, Layout, Match, Overflow, Rect, Snapshot};
     use anyhow::{anyhow, Result};
+    use std::cmp::max;
     use std::str::FromStr;
 
     #[derive(Debug, Default)]
     pub struct Config {
         pub font: Font,
-        pub options: Vec<String>,
+        pub options: Vec<Item>,
+        pub matcher: Match,
+        pub query: String,
+        /// indices into `options` of the buttons currently shown, best match first
+        pub visible: Vec<usize>,
         pub nf: u32,
         pub nb: u32,
         pub sf: u32,
         pub sb: u32,
-        pub button_dim: (usize, usize),
+        pub hf: u32,
+        pub hb: u32,
+        /// (width, height) of the button for each option
+        pub button_sizes: Vec<(usize, usize)>,
         pub border: usize,
-        pub should_close: bool,
+        pub padding: usize,
+        pub overflow: Overflow,
+        pub layout: Layout,
+        /// the size the menu asks for
+        pub bounds: (usize, usize),
+        pub placement: Placement,
+        /// what to print once an item is picked
+        pub format: Format,
+        /// render to a file and exit rather than show a surface
+        pub snapshot: Option<Snapshot>,
+        /// set once the menu is done, to how it ended
+        pub exit: Option<Exit>,
     }
 
     impl Config {
-        pub fn buttons_bounds(&self) -> (usize, usize) {
-            (
-                self.border + self.options.len() * (self.button_dim.0 + self.border),
-                self.border + self.button_dim.1 + self.border,
-            )
+        /// Lay the visible buttons out to fill `size`, 0 where they get their own size
+        pub fn arrange(&self, size: (usize, usize)) -> Sheet {
+            let top = self.buttons_top() - self.border;
+            let sizes: Vec<_> = self.visible.iter().map(|&i| self.button_sizes[i]).collect();
+            let fill = match size {
+                (width, 0) => (width, 0),
+                (width, height) => (width, max(height.saturating_sub(top), 1)),
+            };
+            let (rects, _) = self.layout.place(&sizes, self.border, fill);
+            let pick = |size, bound| if size == 0 { bound } else { size };
+            Sheet {
+                size,
+                bounds: (pick(size.0, self.bounds.0), pick(size.1, self.bounds.1)),
+                rects: rects.into_iter().map(|r| r.offset(0, top)).collect(),
+            }
+        }
+
+        pub fn query_bounds(&self, sheet: &Sheet) -> (i32, i32, i32, i32) {
+            let (left, top) = (self.border, self.border);
+            // squeezed narrower than its borders, the bar is just empty
+            let right = max(left, sheet.bounds.0.saturating_sub(self.border));
+            let bottom = top + self.font.height();
+            (left as i32, right as i32, top as i32, bottom as i32)
+        }
+
+        fn buttons_top(&self) -> usize {
+            self.border + self.font.height() + self.border
+        }
+
+        /// Size each option's button, either `Fixed`, or from its label plus padding
+        pub fn size_buttons(&mut self, sizing: Sizing, height: usize) {
+            let padding = self.padding;
+            let height = match (sizing, height) {
+                (Sizing::Fixed(width), 0) => width,
+                (_, 0) => self.font.height() + 2 * padding,
+                (_, height) => height,
+            };
+            let font = &self.font;
+            let label_width =
+                |opt: &Item| font.glyphs(&opt.text()).width.ceil() as usize + 2 * padding;
+            let widest = self.options.iter().map(label_width).max().unwrap_or(0);
+            // buttons sharing a column can trade places when filtering, so only the
+            // single row layout can give every button its own width
+            let own_width = sizing == Sizing::Fit && self.layout.grid(self.options.len()).1 == 1;
+            self.button_sizes = (self.options.iter())
+                .map(|opt| match sizing {
+                    Sizing::Fixed(width) => (width, height),
+                    _ if own_width => (label_width(opt), height),
+                    _ => (widest, height),
+                })
+                .collect();
+
+            let top = self.buttons_top() - self.border;
+            let (_, (width, height)) = (self.layout).place(&self.button_sizes, self.border, (0, 0));
+            self.bounds = (width, top + height);
+        }
+
+        /// The option shown on button `i`, if it can be picked
+        pub fn option(&self, i: usize) -> Option<usize> {
+            (self.visible.get(i).copied()).filter(|&opt| !self.options[opt].disabled)
+        }
+
+        /// The shown option whose hotkey is `text`, if it can be picked
+        pub fn hotkey(&self, text: &str) -> Option<usize> {
+            let mut chars = text.chars();
+            let key = match (chars.next(), chars.next()) {
+                (Some(key), None) => key,
+                _ => return None,
+            };
+            (self.visible.iter().copied())
+                .find(|&opt| self.options[opt].hotkey == Some(key) && !self.options[opt].disabled)
+        }
+
+        pub fn refilter(&mut self) {
+            let (matcher, query) = (self.matcher, self.query.to_lowercase());
+            let mut scored: Vec<_> = (self.options.iter().enumerate())
+                .filter_map(|(i, opt)| {
+                    matcher
+                        .score(&query, &opt.label.to_lowercase())
+                        .map(|s| (s, i))
+                })
+                .collect();
+            scored.sort();
+            self.visible = scored.into_iter().map(|(_, i)| i).collect();
         }
+    }
+
+    /// The menu laid out for one surface, as `Config::arrange` gives it
+    #[derive(Debug, Default, Clone)]
+    pub struct Sheet {
+        /// the size the compositor gave it, 0 where it left that to us
+        pub size: (usize, usize),
+        /// the size it is drawn at
+        pub bounds: (usize, usize),
+        /// where each visible button is drawn
+        pub rects: Vec<Rect>,
+    }
 
+    impl Sheet {
         pub fn in_button(&self, x: usize, y: usize) -> Option<usize> {
-            let (border, (bw, bh)) = (self.border, self.button_dim);
-            if y >= border && y < border + bh && x >= border && (x - border) % (bw + border) < bw {
-                Some((x - border) / (bw + border))
-            } else {
-                None
-            }
+            self.rects.iter().position(|r| r.contains(x, y))
         }
 
         pub fn button_bounds(&self, i: usize) -> (i32, i32, i32, i32) {
-            let (border, (bw, bh)) = (self.border, self.button_dim);
-            let left = border + i * (bw + border);
-            let right = left + bw;
-            let top = border;
-            let bottom = top + bh;
-            (left as i32, right as i32, top as i32, bottom as i32)
+            let r = self.rects[i];
+            (r.left as i32, r.right as i32, r.top as i32, r.bottom as i32)
+        }
+    }
+
+    /// One line of input, a label to show and match against and the value printed
+    /// when it is picked
+    #[derive(Debug, Default, Clone, PartialEq)]
+    pub struct Item {
+        pub label: String,
+        pub value: String,
+        /// as it was read, or for JSON input its object on one line, keys in their order
+        pub line: String,
+        /// the label's color in place of -nf
+        pub color: Option<u32>,
+        /// drawn before the label, such as a glyph from a symbol font
+        pub icon: Option<String>,
+        /// drawn faded, and can't be picked
+        pub disabled: bool,
+        /// picks the item when typed before anything else
+        pub hotkey: Option<char>,
+        /// shown in the query bar while the item is hovered or focused, until something is typed
+        pub tooltip: Option<String>,
+    }
+
+    impl Item {
+        /// Split `line` at the first `delimiter`, if given and there is one,
+        /// otherwise the whole line is both label and value
+        pub fn parse(line: &str, delimiter: Option<&str>) -> Item {
+            let (label, value) = (delimiter)
+                .and_then(|delimiter| line.split_once(delimiter))
+                .unwrap_or((line, line));
+            Item {
+                label: label.to_owned(),
+                value: value.to_owned(),
+                line: line.to_owned(),
+                ..Item::default()
+            }
+        }
+
+        /// What its button shows
+        pub fn text(&self) -> String {
+            match &self.icon {
+                Some(icon) => format!("{} {}", icon, self.label),
+                None => self.label.clone(),
+            }
+        }
+    }
+
+    /// How the menu ended, which is its exit status
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Exit {
+        /// an item was picked and printed, or the snapshot written
+        Selected = 0,
+        /// dismissed with Escape, or by closing its window
+        Cancelled = 1,
+        /// the compositor took the menu away, or every output it was on
+        Closed = 2,
+        /// stdin had nothing to pick from
+        NoInput = 3,
+        /// anything else went wrong, as printed to stderr
+        Error = 4,
+    }
+
+    /// How wide buttons are
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Sizing {
+        Fixed(usize),
+        /// as wide as the widest label
+        Uniform,
+        /// as wide as their own label
+        Fit,
+    }
+
+    impl FromStr for Sizing {
+        type Err = anyhow::Error;
+        fn from_str(s: &str) -> Result<Self> {
+            match s {
+                "auto" => Ok(Sizing::Uniform),
+                "fit" => Ok(Sizing::Fit),
+                _ => s.parse().map(Sizing::Fixed).map_err(|_| {
+                    anyhow!("button width must be a number of pixels, 'auto', or 'fit'")
+                }),
+            }
+        }
+    }
+
+    /// Where a layer surface goes, ignored for toplevels besides `namespace`
+    #[derive(Debug)]
+    pub struct Placement {
+        /// no edges centers the menu
+        pub anchor: layer_surface::Anchor,
+        /// (top, right, bottom, left)
+        pub margin: (i32, i32, i32, i32),
+        pub layer: Layer,
+        pub exclusive_zone: i32,
+        /// the layer surface namespace, or app_id of a toplevel
+        pub namespace: String,
+        pub output: OutputChoice,
+    }
+
+    /// Which outputs to show the menu on
+    #[derive(Debug, Default, Clone, PartialEq)]
+    pub enum OutputChoice {
+        /// wherever the compositor puts it, usually the focused output
+        #[default]
+        Focused,
+        /// by the order outputs are announced in
+        Index(usize),
+        /// by connector name like "DP-1"
+        Name(String),
+        /// on every output at once
+        All,
+    }
+
+    impl FromStr for OutputChoice {
+        type Err = anyhow::Error;
+        fn from_str(s: &str) -> Result<Self> {
+            match s {
+                "focused" => Ok(OutputChoice::Focused),
+                "all" => Ok(OutputChoice::All),
+                "" => Err(anyhow!(
+                    "output must be 'focused', 'all', an index, or a name"
+                )),
+                _ => Ok(s
+                    .parse()
+                    .map_or_else(|_| OutputChoice::Name(s.to_owned()), OutputChoice::Index)),
+            }
+        }
+    }
+
+    impl Default for Placement {
+        fn default() -> Self {
+            Placement {
+                anchor: layer_surface::Anchor::empty(),
+                margin: (0, 0, 0, 0),
+                layer: Layer::Overlay,
+                exclusive_zone: 0,
+                namespace: String::from("wtmenu"),
+                output: OutputChoice::default(),
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct Anchor(pub layer_surface::Anchor);
+
+    static ANCHOR_FORMAT_MSG: &str =
+        "anchor must be 'center' or edges 'top', 'bottom', 'left', 'right' joined by '-' or ','";
+
+    impl FromStr for Anchor {
+        type Err = anyhow::Error;
+        fn from_str(s: &str) -> Result<Self> {
+            let mut anchor = layer_surface::Anchor::empty();
+            if s == "center" {
+                return Ok(Anchor(anchor));
+            }
+            for edge in s.split(['-', ',']) {
+                anchor |= match edge {
+                    "top" => layer_surface::Anchor::Top,
+                    "bottom" => layer_surface::Anchor::Bottom,
+                    "left" => layer_surface::Anchor::Left,
+                    "right" => layer_surface::Anchor::Right,
+                    _ => return Err(anyhow!(ANCHOR_FORMAT_MSG)),
+                };
+            }
+            Ok(Anchor(anchor))
+        }
+    }
+
+    /// Margins like CSS, all sides, vertical and horizontal, or top, right, bottom, left
+    #[derive(Debug, Clone, Copy)]
+    pub struct Margin(pub (i32, i32, i32, i32));
+
+    static MARGIN_FORMAT_MSG: &str = "margin must be 1, 2, or 4 comma separated numbers of pixels";
+
+    impl FromStr for Margin {
+        type Err = anyhow::Error;
+        fn from_str(s: &str) -> Result<Self> {
+            let sides = (s.split(','))
+                .map(|n| n.trim().parse())
+                .collect::<Result<Vec<i32>, _>>()
+                .map_err(|_| anyhow!(MARGIN_FORMAT_MSG))?;
+            match sides[..] {
+                [all] => Ok(Margin((all, all, all, all))),
+                [vertical, horizontal] => Ok(Margin((vertical, horizontal, vertical, horizontal))),
+                [top, right, bottom, left] => Ok(Margin((top, right, bottom, left))),
+                _ => Err(anyhow!(MARGIN_FORMAT_MSG)),
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct ShellLayer(pub Layer);
+
+    impl FromStr for ShellLayer {
+        type Err = anyhow::Error;
+        fn from_str(s: &str) -> Result<Self> {
+            match s {
+                "background" => Ok(ShellLayer(Layer::Background)),
+                "bottom" => Ok(ShellLayer(Layer::Bottom)),
+                "top" => Ok(ShellLayer(Layer::Top)),
+                "overlay" => Ok(ShellLayer(Layer::Overlay)),
+                _ => Err(anyhow!(
+                    "layer must be one of 'background', 'bottom', 'top', or 'overlay'"
+                )),
+            }
         }
     }
 
@@ -108,311 +454,2219 @@ mod conf {
         }
     }
 }
-use conf::{Argb, Config};
+use conf::{
+    Anchor, Argb, Config, Exit, Item, Margin, OutputChoice, Placement, Sheet, ShellLayer, Sizing,
+};
 
-use font::Font;
-mod font {
-    use anyhow::{Context, Result};
-    use rusttype::{self, point, Font as rtFont, Point, PositionedGlyph, Scale};
+use layout::{Direction, Layout, Rect};
+mod layout {
+    use anyhow::{anyhow, Result};
+    use std::cmp::max;
+    use std::str::FromStr;
 
-    #[derive(Debug)]
-    pub struct Font {
-        font: rtFont<'static>,
-        scale: Scale,
-        offset: Point<f32>,
+    #[derive(Debug, Default, Clone, Copy, PartialEq)]
+    pub struct Rect {
+        pub left: usize,
+        pub right: usize,
+        pub top: usize,
+        pub bottom: usize,
     }
 
-    #[derive(Debug)]
-    pub struct Glyphs<'f> {
-        glyphs: Vec<PositionedGlyph<'f>>,
-        pub width: f32,
-        pub height: f32,
+    impl Rect {
+        pub fn contains(&self, x: usize, y: usize) -> bool {
+            x >= self.left && x < self.right && y >= self.top && y < self.bottom
+        }
+
+        pub fn offset(self, x: usize, y: usize) -> Rect {
+            Rect {
+                left: self.left + x,
+                right: self.right + x,
+                top: self.top + y,
+                bottom: self.bottom + y,
+            }
+        }
     }
 
-    impl Default for Font {
-        fn default() -> Self {
-            let font =
-                rtFont::try_from_bytes(include_bytes!("../SourceCodePro-Regular.otf") as &[u8])
-                    .expect("Failed constructing a Font from bytes");
-            Font::new(font)
+    /// How buttons are arranged, always filled row by row
+    #[derive(Debug, Default, Clone, Copy, PartialEq)]
+    pub enum Layout {
+        #[default]
+        Horizontal,
+        Vertical,
+        Grid(usize),
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Direction {
+        Left,
+        Right,
+        Up,
+        Down,
+    }
+
+    static LAYOUT_FORMAT_MSG: &str =
+        "layout must be one of 'horizontal', 'vertical', or 'grid:N' with N > 0 columns";
+
+    impl FromStr for Layout {
+        type Err = anyhow::Error;
+        fn from_str(s: &str) -> Result<Self> {
+            match s {
+                "horizontal" => Ok(Layout::Horizontal),
+                "vertical" => Ok(Layout::Vertical),
+                _ if s.starts_with("grid:") => match s["grid:".len()..].parse() {
+                    Ok(0) | Err(_) => Err(anyhow!(LAYOUT_FORMAT_MSG)),
+                    Ok(columns) => Ok(Layout::Grid(columns)),
+                },
+                _ => Err(anyhow!(LAYOUT_FORMAT_MSG)),
+            }
         }
     }
 
-    impl Font {
-        fn new(font: rtFont<'static>) -> Self {
-            let scale = Scale::uniform(40.0);
-            let v_metrics = font.v_metrics(scale);
-            let offset = point(0.0, v_metrics.ascent);
-            Font {
-                font,
-                scale,
-                offset,
+    impl Layout {
+        /// (columns, rows) needed to show `n` buttons
+        pub fn grid(self, n: usize) -> (usize, usize) {
+            let n = std::cmp::max(n, 1);
+            match self {
+                Layout::Horizontal => (n, 1),
+                Layout::Vertical => (1, n),
+                // no empty columns when there are fewer buttons
+                Layout::Grid(columns) => {
+                    let columns = std::cmp::min(columns, n);
+                    (columns, n.div_ceil(columns))
+                }
             }
         }
 
-        pub fn load<P: AsRef<std::path::Path>>(name: &P) -> Result<Font> {
-            let bytes = std::fs::read(name)?;
-            let font = rtFont::try_from_vec(bytes).context("Failed loading the default font")?;
-            Ok(Self::new(font))
+        /// Lay out buttons of `sizes`, every button stretched to its row and column,
+        /// then the columns and rows stretched or shrunk to fill `fill` where it isn't 0.
+        /// Returns each button's rect and the size of the whole area, borders included.
+        pub fn place(
+            self,
+            sizes: &[(usize, usize)],
+            border: usize,
+            fill: (usize, usize),
+        ) -> (Vec<Rect>, (usize, usize)) {
+            let (columns, rows) = self.grid(sizes.len());
+            let (mut widths, mut heights) = (vec![0; columns], vec![0; rows]);
+            for (i, &(w, h)) in sizes.iter().enumerate() {
+                widths[i % columns] = max(widths[i % columns], w);
+                heights[i / columns] = max(heights[i / columns], h);
+            }
+            stretch(&mut widths, fill.0, border);
+            stretch(&mut heights, fill.1, border);
+            let offsets = |sizes: &[usize]| {
+                sizes.iter().fold(vec![border], |mut acc, size| {
+                    acc.push(acc[acc.len() - 1] + size + border);
+                    acc
+                })
+            };
+            let (lefts, tops) = (offsets(&widths), offsets(&heights));
+
+            let rects = (0..sizes.len())
+                .map(|i| {
+                    let (column, row) = (i % columns, i / columns);
+                    Rect {
+                        left: lefts[column],
+                        right: lefts[column] + widths[column],
+                        top: tops[row],
+                        bottom: tops[row] + heights[row],
+                    }
+                })
+                .collect();
+            (rects, (lefts[columns], tops[rows]))
         }
 
-        pub fn glyphs(&self, s: &str) -> Glyphs {
-            let glyphs: Vec<_> = self.font.layout(s, self.scale, self.offset).collect();
-            let width = glyphs
-                .last()
-                .map(|g| g.position().x as f32 + g.unpositioned().h_metrics().advance_width)
-                .unwrap_or(0.0);
+        /// The button next to `i` of the first `shown` buttons, wrapping around the edges
+        pub fn neighbor(self, n: usize, shown: usize, i: usize, dir: Direction) -> usize {
+            let (columns, rows) = self.grid(n);
+            let shown = std::cmp::max(shown, 1);
+            let i = std::cmp::min(i, shown - 1);
+            let single_line = columns == 1 || rows == 1;
+            match dir {
+                Direction::Right => (i + 1) % shown,
+                Direction::Left => (i + shown - 1) % shown,
+                Direction::Down if single_line => (i + 1) % shown,
+                Direction::Up if single_line => (i + shown - 1) % shown,
+                Direction::Down if i + columns < shown => i + columns,
+                Direction::Down => i % columns,
+                Direction::Up if i >= columns => i - columns,
+                // the last shown button in this column
+                Direction::Up => {
+                    let column = i % columns;
+                    column + (shown - 1 - column) / columns * columns
+                }
+            }
+        }
+    }
 
-            Glyphs {
-                glyphs,
-                width,
-                height: self.scale.y,
+    /// Scale `sizes` in proportion, so with borders around them they add up to `total`
+    fn stretch(sizes: &mut [usize], total: usize, border: usize) {
+        let natural: usize = sizes.iter().sum();
+        let available = total.saturating_sub(border * (sizes.len() + 1));
+        if total == 0 || natural == 0 {
+            return;
+        }
+        // scale the running total, so rounding never loses or gains a pixel
+        let (mut sum, mut end) = (0, 0);
+        for size in sizes.iter_mut() {
+            sum += *size;
+            let next = sum * available / natural;
+            *size = next - end;
+            end = next;
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn grid_has_no_more_columns_than_buttons() {
+            assert_eq!(Layout::Grid(3).grid(7), (3, 3));
+            assert_eq!(Layout::Grid(50).grid(3), (3, 1));
+            assert_eq!(Layout::Grid(3).grid(0), (1, 1));
+        }
+
+        #[test]
+        fn grid_up_and_down_wrap_around_a_partial_last_row() {
+            // 0 1 2
+            // 3 4 5
+            // 6
+            let step = |i, dir| Layout::Grid(3).neighbor(7, 7, i, dir);
+            assert_eq!(step(3, Direction::Down), 6);
+            assert_eq!(step(4, Direction::Down), 1);
+            assert_eq!(step(6, Direction::Down), 0);
+            assert_eq!(step(0, Direction::Up), 6);
+            assert_eq!(step(1, Direction::Up), 4);
+            assert_eq!(step(4, Direction::Up), 1);
+            assert_eq!(step(6, Direction::Right), 0);
+            assert_eq!(step(0, Direction::Left), 6);
+        }
+
+        #[test]
+        fn filtering_keeps_the_grid_shape() {
+            // typing left 4 of the 7 buttons shown, in the same 3 columns
+            let step = |i, dir| Layout::Grid(3).neighbor(7, 4, i, dir);
+            assert_eq!(step(1, Direction::Down), 1);
+            assert_eq!(step(0, Direction::Up), 3);
+            assert_eq!(step(2, Direction::Up), 2);
+            // past the end, as focus can be when the buttons shrink
+            assert_eq!(step(5, Direction::Right), 0);
+        }
+
+        #[test]
+        fn a_single_line_steps_through_every_button() {
+            let step = |layout: Layout, i, dir| layout.neighbor(3, 3, i, dir);
+            assert_eq!(step(Layout::Horizontal, 2, Direction::Down), 0);
+            assert_eq!(step(Layout::Horizontal, 0, Direction::Up), 2);
+            assert_eq!(step(Layout::Vertical, 2, Direction::Right), 0);
+            assert_eq!(step(Layout::Vertical, 1, Direction::Up), 0);
+        }
+
+        #[test]
+        fn stretching_never_loses_a_pixel() {
+            let mut sizes = [100, 100, 100];
+            stretch(&mut sizes, 600, 1);
+            assert_eq!(sizes, [198, 199, 199]);
+            assert_eq!(sizes.iter().sum::<usize>() + 4, 600);
+            // narrower than the borders alone
+            stretch(&mut sizes, 3, 2);
+            assert_eq!(sizes, [0, 0, 0]);
+        }
+    }
+}
+
+use output::Format;
+mod output {
+    use super::Item;
+    use anyhow::{anyhow, Result};
+    use std::str::FromStr;
+
+    /// What is printed for the picked item
+    #[derive(Debug, Default, Clone, PartialEq)]
+    pub enum Format {
+        #[default]
+        Value,
+        /// the input line as read
+        Line,
+        /// zero-based, in input order
+        Index,
+        /// an object of every field
+        Json,
+        Template(Vec<Piece>),
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Piece {
+        Text(String),
+        Field(Field),
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Field {
+        Index,
+        /// zero-based position among the buttons shown when it was picked
+        Button,
+        Label,
+        Value,
+        Line,
+    }
+
+    static FORMAT_MSG: &str =
+        "format must be 'value', 'line', 'index', 'json', or a template with {placeholders}";
+
+    impl FromStr for Format {
+        type Err = anyhow::Error;
+        fn from_str(s: &str) -> Result<Self> {
+            match s {
+                "value" => Ok(Format::Value),
+                "line" => Ok(Format::Line),
+                "index" => Ok(Format::Index),
+                "json" => Ok(Format::Json),
+                _ if s.contains(['{', '}']) => template(s).map(Format::Template),
+                _ => Err(anyhow!(FORMAT_MSG)),
             }
         }
     }
 
-    impl<'f> Glyphs<'f> {
-        pub fn render(self, mut d: impl FnMut(usize, usize, u8)) {
-            let (width, height) = (self.width.ceil(), self.height.ceil());
+    impl FromStr for Field {
+        type Err = anyhow::Error;
+        fn from_str(s: &str) -> Result<Self> {
+            match s {
+                "index" => Ok(Field::Index),
+                "button" => Ok(Field::Button),
+                "label" => Ok(Field::Label),
+                "value" => Ok(Field::Value),
+                "line" => Ok(Field::Line),
+                _ => Err(anyhow!(
+                    "unknown placeholder {{{}}}, expected index, button, label, value or line",
+                    s
+                )),
+            }
+        }
+    }
 
-            self.glyphs
-                .iter()
-                .filter_map(|g| g.pixel_bounding_box().map(|bb| (g, bb)))
-                .for_each(|(g, bb)| {
-                    g.draw(|x, y, v| {
-                        let v = (v * 255.0).ceil() as u8;
-                        let x = x as i32 + bb.min.x;
-                        let y = y as i32 + bb.min.y;
-                        if x >= 0 && x < width as i32 && y >= 0 && y < height as i32 {
-                            d(x as usize, y as usize, v);
+    /// Split `s` into text and `{field}`s, with `{{` and `}}` for literal braces
+    fn template(s: &str) -> Result<Vec<Piece>> {
+        let (mut pieces, mut text) = (vec![], String::new());
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, chars.peek()) {
+                ('{', Some('{')) | ('}', Some('}')) => {
+                    chars.next();
+                    text.push(c);
+                }
+                ('{', _) => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => Err(anyhow!("unclosed '{{' in format"))?,
                         }
-                    })
+                    }
+                    let field = name.parse()?;
+                    pieces.push(Piece::Text(std::mem::take(&mut text)));
+                    pieces.push(Piece::Field(field));
+                }
+                ('}', _) => Err(anyhow!(
+                    "unmatched '}}' in format, write '}}}}' for a brace"
+                ))?,
+                _ => text.push(c),
+            }
+        }
+        pieces.push(Piece::Text(text));
+        Ok(pieces)
+    }
+
+    impl Format {
+        /// What to print for `item`, the `index`th of the input, picked from `button`
+        pub fn print(&self, item: &Item, index: usize, button: usize) -> String {
+            let field = |field| match field {
+                Field::Index => index.to_string(),
+                Field::Button => button.to_string(),
+                Field::Label => item.label.clone(),
+                Field::Value => item.value.clone(),
+                Field::Line => item.line.clone(),
+            };
+            match self {
+                Format::Value => field(Field::Value),
+                Format::Line => field(Field::Line),
+                Format::Index => field(Field::Index),
+                Format::Json => serde_json::json!({
+                    "index": index,
+                    "label": item.label,
+                    "value": item.value,
+                    "button": button,
                 })
+                .to_string(),
+                Format::Template(pieces) => (pieces.iter())
+                    .map(|piece| match piece {
+                        Piece::Text(text) => text.clone(),
+                        Piece::Field(f) => field(*f),
+                    })
+                    .collect(),
+            }
         }
     }
 }
 
-#[derive(Debug)]
-struct Registry {
-    compositor: Main<WlCompositor>,
-    seat: Main<WlSeat>,
-    shm: Main<WlShm>,
-    wmbase: Main<XdgWmBase>,
-    layer_shell: Main<LayerShell>,
-}
+mod json {
+    use super::{Argb, Item};
+    use anyhow::{anyhow, Result};
+    use serde::de::{Deserialize, Deserializer, Error};
+    use serde_json::{Map, Value};
 
-#[derive(Debug, Default)]
-struct Pointer {
-    pos: Option<(f64, f64)>,
-    pos_prev: Option<(f64, f64)>,
-    btn: Option<wl_pointer::ButtonState>,
-    btn_prev: Option<wl_pointer::ButtonState>,
-    frame: bool,
-}
+    /// An item read from an object, which serde_json can place errors in
+    struct JsonItem(Item);
 
-#[derive(Debug)]
-struct Surface {
-    wl: Main<WlSurface>,
-    layer: Main<LayerSurface>,
-    committed: bool,
-    configured: bool,
-}
+    impl<'de> Deserialize<'de> for JsonItem {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let fields = Map::deserialize(deserializer)?;
+            item(fields).map(JsonItem).map_err(D::Error::custom)
+        }
+    }
 
-#[derive(Debug)]
-struct Data {
-    cfg: Config,
-    registry: Registry,
-    ptr: Pointer,
-    seat_cap: wl_seat::Capability,
-    shm_formats: Vec<wl_shm::Format>,
-    buffer: ShmPixelBuffer,
-    surface: Surface,
-    rendered: bool,
+    /// Read `input` as an array of objects, or as one object after another,
+    /// such as one per line
+    pub fn items(input: &str) -> Result<Vec<Item>> {
+        if input.trim_start().starts_with('[') {
+            let items: Vec<JsonItem> = serde_json::from_str(input)
+                .map_err(|err| anyhow!("invalid JSON input: {}", err))?;
+            return Ok(items.into_iter().map(|item| item.0).collect());
+        }
+        let mut stream = serde_json::Deserializer::from_str(input).into_iter::<JsonItem>();
+        let mut items = Vec::new();
+        while let Some(item) = stream.next() {
+            match item {
+                Ok(item) => items.push(item.0),
+                // the stream leaves errors about a whole object unplaced, so
+                // place them where that object starts
+                Err(err) if err.line() == 0 => {
+                    let read = &input[..stream.byte_offset()];
+                    let line = read.matches('\n').count() + 1;
+                    let start = read.rfind('\n').map_or(0, |at| at + 1);
+                    let column = read[start..].chars().count() + 1;
+                    return Err(anyhow!(
+                        "invalid JSON input: {} at line {} column {}",
+                        err,
+                        line,
+                        column
+                    ));
+                }
+                Err(err) => return Err(anyhow!("invalid JSON input: {}", err)),
+            }
+        }
+        Ok(items)
+    }
+
+    fn item(fields: Map<String, Value>) -> Result<Item, String> {
+        let string = |key| match fields.get(key) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s.clone())),
+            Some(other) => Err(format!("{:?} must be a string, not {}", key, other)),
+        };
+        let label = string("label")?.ok_or("every option needs a \"label\"")?;
+        let color = match string("color")? {
+            Some(color) => Some(color.parse::<Argb>().map_err(|e| e.to_string())?.0),
+            None => None,
+        };
+        let hotkey = match string("hotkey")? {
+            Some(key) if key.chars().count() == 1 => key.chars().next(),
+            Some(key) => Err(format!("\"hotkey\" must be one character, not {:?}", key))?,
+            None => None,
+        };
+        let disabled = match fields.get("disabled") {
+            None | Some(Value::Null) => false,
+            Some(Value::Bool(disabled)) => *disabled,
+            Some(other) => Err(format!("\"disabled\" must be true or false, not {}", other))?,
+        };
+        Ok(Item {
+            value: string("value")?.unwrap_or_else(|| label.clone()),
+            icon: string("icon")?,
+            tooltip: string("tooltip")?,
+            label,
+            color,
+            disabled,
+            hotkey,
+            line: Value::Object(fields).to_string(),
+        })
+    }
 }
 
-impl Data {
-    fn new(cfg: Config, mut registry: Registry) -> Data {
-        let seat = &mut registry.seat;
-        filter!(seat, data,
-            wl_seat::Event::Capabilities{capabilities} => data.seat_cap = capabilities
-        );
-        let pointer = seat.get_pointer();
-        filter!(pointer, data,
-            wl_pointer::Event::Enter { surface_x, surface_y, .. } => {
-                data.ptr.pos.replace((surface_x, surface_y));
-            },
-            wl_pointer::Event::Leave { .. } => {
-                data.ptr.pos.take();
-                data.ptr.btn.take();
-            },
-            wl_pointer::Event::Motion { surface_x, surface_y, .. } => {
-                data.ptr.pos.replace((surface_x, surface_y));
-            },
-            wl_pointer::Event::Button { button: 0x110, state, .. } => {
-                // 0x110 is BUTTON1
-                data.ptr.btn.replace(state);
+use filter::Match;
+mod filter {
+    use anyhow::{anyhow, Result};
+    use std::str::FromStr;
+
+    #[derive(Debug, Default, Clone, Copy, PartialEq)]
+    pub enum Match {
+        #[default]
+        Substring,
+        Prefix,
+        Fuzzy,
+    }
+
+    impl FromStr for Match {
+        type Err = anyhow::Error;
+        fn from_str(s: &str) -> Result<Self> {
+            match s {
+                "substring" => Ok(Match::Substring),
+                "prefix" => Ok(Match::Prefix),
+                "fuzzy" => Ok(Match::Fuzzy),
+                _ => Err(anyhow!(
+                    "match mode must be one of 'substring', 'prefix', or 'fuzzy'"
+                )),
+            }
+        }
+    }
+
+    impl Match {
+        /// Score `candidate` against `query`, lower is a better match, None is no match.
+        pub fn score(self, query: &str, candidate: &str) -> Option<(usize, usize)> {
+            match self {
+                Match::Prefix => Some((0, 0)).filter(|_| candidate.starts_with(query)),
+                // prefix matches before other substring matches, like dmenu
+                Match::Substring => candidate
+                    .find(query)
+                    .map(|i| (if i == 0 { 0 } else { 1 }, 0)),
+                Match::Fuzzy => fuzzy(query, candidate),
+            }
+        }
+    }
+
+    /// Match `query` as a subsequence of `candidate`, scored by (span, start) of the
+    /// tightest window ending at the first complete match.
+    fn fuzzy(query: &str, candidate: &str) -> Option<(usize, usize)> {
+        let query: Vec<char> = query.chars().collect();
+        let candidate: Vec<char> = candidate.chars().collect();
+        if query.is_empty() {
+            return Some((0, 0));
+        }
+
+        let mut qi = 0;
+        let end = candidate.iter().position(|&c| {
+            if c == query[qi] {
+                qi += 1;
+            }
+            qi == query.len()
+        })?;
+
+        let mut qi = query.len();
+        let start = (0..=end).rev().find(|&ci| {
+            if candidate[ci] == query[qi - 1] {
+                qi -= 1;
+            }
+            qi == 0
+        })?;
+
+        Some((end - start, start))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn ranked(matcher: Match, query: &str, candidates: &[&'static str]) -> Vec<&'static str> {
+            // ties keep their order, as options do in Config::refilter
+            let mut scored: Vec<_> = (candidates.iter().enumerate())
+                .filter_map(|(i, &c)| matcher.score(query, c).map(|s| (s, i, c)))
+                .collect();
+            scored.sort();
+            scored.into_iter().map(|(_, _, c)| c).collect()
+        }
+
+        #[test]
+        fn substring_ranks_prefixes_first() {
+            let candidates = ["wifi", "firefox", "profile", "thunderbird"];
+            assert_eq!(
+                ranked(Match::Substring, "fi", &candidates),
+                ["firefox", "wifi", "profile"]
+            );
+            assert_eq!(ranked(Match::Prefix, "fi", &candidates), ["firefox"]);
+        }
+
+        #[test]
+        fn fuzzy_ranks_tight_spans_then_early_starts() {
+            assert_eq!(fuzzy("ab", "ab"), Some((1, 0)));
+            assert_eq!(fuzzy("ab", "xab"), Some((1, 1)));
+            assert_eq!(fuzzy("ab", "a_b"), Some((2, 0)));
+            // the window ends at the first complete match, then shrinks from there
+            assert_eq!(fuzzy("ab", "a_ab"), Some((1, 2)));
+            assert_eq!(fuzzy("ab", "ba"), None);
+            assert_eq!(fuzzy("", "anything"), Some((0, 0)));
+            assert_eq!(
+                ranked(Match::Fuzzy, "ab", &["a_b", "xab", "ba", "ab"]),
+                ["ab", "xab", "a_b"]
+            );
+        }
+    }
+}
+
+use font::{Font, Glyphs, Overflow};
+mod font {
+    use anyhow::{anyhow, Context, Result};
+    use rusttype::{self, point, Font as rtFont, PositionedGlyph, Scale};
+    use std::path::{Path, PathBuf};
+    use std::str::FromStr;
+
+    pub const DEFAULT_SIZE: f32 = 40.0;
+
+    /// Faces in order of preference, each character is drawn from the first one that has it
+    #[derive(Debug)]
+    pub struct Font {
+        faces: Vec<rtFont<'static>>,
+        scale: Scale,
+        ascent: f32,
+    }
+
+    #[derive(Debug)]
+    pub struct Glyphs<'f> {
+        glyphs: Vec<PositionedGlyph<'f>>,
+        pub width: f32,
+        pub height: f32,
+    }
+
+    impl Default for Font {
+        fn default() -> Self {
+            Font::embedded(DEFAULT_SIZE)
+        }
+    }
+
+    impl Font {
+        fn new(faces: Vec<rtFont<'static>>, size: f32) -> Self {
+            let scale = Scale::uniform(size);
+            // every face shares the first one's baseline
+            let ascent = faces[0].v_metrics(scale).ascent;
+            Font {
+                faces,
+                scale,
+                ascent,
+            }
+        }
+
+        fn embedded_face() -> rtFont<'static> {
+            rtFont::try_from_bytes(include_bytes!("../SourceCodePro-Regular.otf") as &[u8])
+                .expect("Failed constructing a Font from bytes")
+        }
+
+        pub fn embedded(size: f32) -> Font {
+            Font::new(vec![Font::embedded_face()], size)
+        }
+
+        /// Load each font spec in turn as a fallback for the ones before it,
+        /// with the embedded font last
+        pub fn open_all(specs: &[String], size: f32) -> Result<Font> {
+            let mut faces = specs
+                .iter()
+                .map(|spec| {
+                    Font::open_face(spec).with_context(|| format!("failed to load font {:?}", spec))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            faces.push(Font::embedded_face());
+            Ok(Font::new(faces, size))
+        }
+
+        /// Load a font from a file path, or else by family name like "Noto Sans:bold".
+        /// No family name has a '/' in it, so such a spec is a path even if it is missing.
+        fn open_face(spec: &str) -> Result<rtFont<'static>> {
+            let (path, index) = if spec.contains('/') || Path::new(spec).is_file() {
+                (PathBuf::from(spec), 0)
+            } else {
+                find(spec)?
+            };
+            Font::load_face(&path, index)
+                .with_context(|| format!("failed to load font file {}", path.display()))
+        }
+
+        fn load_face<P: AsRef<Path>>(name: &P, index: u32) -> Result<rtFont<'static>> {
+            let bytes = std::fs::read(name)?;
+            rtFont::try_from_vec_and_index(bytes, index)
+                .ok_or_else(|| anyhow!("not a font rusttype can read"))
+        }
+
+        /// The first face with a glyph for `c`, or the first face to draw its .notdef
+        fn face(&self, c: char) -> usize {
+            (self.faces.iter())
+                .position(|face| face.glyph(c).id().0 != 0)
+                .unwrap_or(0)
+        }
+
+        pub fn height(&self) -> usize {
+            self.scale.y.ceil() as usize
+        }
+
+        pub fn width(&self, s: &str) -> f32 {
+            self.glyphs(s).width
+        }
+
+        /// Break `s` into the lines to draw in a box of `width` by `height`.
+        pub fn fit(&self, s: &str, (width, height): (f32, f32), overflow: Overflow) -> Vec<String> {
+            if overflow == Overflow::Clip || self.width(s) <= width {
+                return vec![s.to_owned()];
+            }
+            match overflow {
+                Overflow::Clip | Overflow::End => vec![self.ellipsize_end(s, width)],
+                Overflow::Middle => vec![self.ellipsize_middle(s, width)],
+                Overflow::Wrap => {
+                    let max_lines = std::cmp::max((height / self.scale.y) as usize, 1);
+                    let mut lines = self.wrap(s, width);
+                    if lines.len() > max_lines {
+                        let rest = lines.split_off(max_lines - 1).join(" ");
+                        lines.push(self.ellipsize_end(&rest, width));
+                    }
+                    lines
+                }
+            }
+        }
+
+        fn ellipsis(&self) -> &'static str {
+            if self.faces.iter().any(|face| face.glyph('…').id().0 != 0) {
+                "…"
+            } else {
+                "..."
+            }
+        }
+
+        fn ellipsize_end(&self, s: &str, width: f32) -> String {
+            let chars: Vec<char> = s.chars().collect();
+            (0..chars.len())
+                .rev()
+                .map(|n| {
+                    let s: String = chars[..n].iter().collect();
+                    s.trim_end().to_owned() + self.ellipsis()
+                })
+                .find(|s| self.width(s) <= width)
+                .unwrap_or_default()
+        }
+
+        fn ellipsize_middle(&self, s: &str, width: f32) -> String {
+            let chars: Vec<char> = s.chars().collect();
+            (0..chars.len())
+                .rev()
+                .map(|n| {
+                    let head: String = chars[..n.div_ceil(2)].iter().collect();
+                    let tail: String = chars[chars.len() - n / 2..].iter().collect();
+                    head.trim_end().to_owned() + self.ellipsis() + tail.trim_start()
+                })
+                .find(|s| self.width(s) <= width)
+                .unwrap_or_default()
+        }
+
+        /// Greedily fill lines word by word, breaking words that are too long on their own.
+        fn wrap(&self, s: &str, width: f32) -> Vec<String> {
+            let (mut lines, mut line) = (vec![], String::new());
+            for word in s.split_whitespace() {
+                let joined = if line.is_empty() {
+                    word.to_owned()
+                } else {
+                    format!("{} {}", line, word)
+                };
+                if self.width(&joined) <= width {
+                    line = joined;
+                    continue;
+                }
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+                for c in word.chars() {
+                    line.push(c);
+                    if line.chars().count() > 1 && self.width(&line) > width {
+                        line.pop();
+                        lines.push(std::mem::replace(&mut line, c.to_string()));
+                    }
+                }
+            }
+            if !line.is_empty() {
+                lines.push(line);
+            }
+            lines
+        }
+
+        pub fn glyphs(&self, s: &str) -> Glyphs<'_> {
+            self.glyphs_scaled(s, 1.0)
+        }
+
+        /// Lay out `s` like `rusttype::Font::layout`, but across faces on one baseline,
+        /// `factor` times bigger than the font's size
+        pub fn glyphs_scaled(&self, s: &str, factor: f32) -> Glyphs<'_> {
+            let scale = Scale::uniform(self.scale.y * factor);
+            let mut glyphs: Vec<PositionedGlyph<'_>> = vec![];
+            let mut x = 0.0;
+            let mut last = None;
+            for c in s.chars() {
+                let i = self.face(c);
+                let face = &self.faces[i];
+                let g = face.glyph(c).scaled(scale);
+                // kerning only applies between glyphs of the same face
+                if let Some((prev_face, prev)) = last {
+                    if prev_face == i {
+                        x += face.pair_kerning(scale, prev, g.id());
+                    }
+                }
+                last = Some((i, g.id()));
+                let advance = g.h_metrics().advance_width;
+                glyphs.push(g.positioned(point(x, self.ascent * factor)));
+                x += advance;
+            }
+            let width = x;
+
+            Glyphs {
+                glyphs,
+                width,
+                height: scale.y,
+            }
+        }
+    }
+
+    /// Directories fonts are installed in, like fontconfig's defaults
+    fn font_dirs() -> Vec<PathBuf> {
+        let home = std::env::var_os("HOME").map(PathBuf::from);
+        let data_home = std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| home.as_ref().map(|h| h.join(".local/share")));
+        let data_dirs = std::env::var("XDG_DATA_DIRS")
+            .ok()
+            .filter(|dirs| !dirs.is_empty())
+            .unwrap_or_else(|| String::from("/usr/local/share:/usr/share"));
+
+        let mut dirs: Vec<PathBuf> = data_home.into_iter().map(|d| d.join("fonts")).collect();
+        dirs.extend(home.as_ref().map(|h| h.join(".fonts")));
+        dirs.extend(data_dirs.split(':').map(|d| Path::new(d).join("fonts")));
+
+        // plain <dir> entries from fontconfig's own config
+        for conf in &["/etc/fonts/fonts.conf", "/etc/fonts/local.conf"] {
+            let conf = std::fs::read_to_string(conf).unwrap_or_default();
+            for entry in conf.split("<dir>").skip(1) {
+                if let Some(dir) = entry.split("</dir>").next().map(str::trim) {
+                    match (dir.strip_prefix("~/"), &home) {
+                        (Some(rest), Some(home)) => dirs.push(home.join(rest)),
+                        (None, _) => dirs.push(PathBuf::from(dir)),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        dirs.dedup();
+        dirs
+    }
+
+    fn font_files(dir: &Path, files: &mut Vec<PathBuf>) {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+            let ext = path
+                .extension()
+                .and_then(|e| e.to_str())
+                .map(str::to_lowercase);
+            if path.is_dir() {
+                font_files(&path, files);
+            } else if let Some("ttf" | "otf" | "ttc" | "otc") = ext.as_deref() {
+                files.push(path);
+            }
+        }
+    }
+
+    /// Find the font file (and index in it) best matching "Family[:style]", where style
+    /// is any of a weight like light or bold, and italic.
+    pub fn find(pattern: &str) -> Result<(PathBuf, u32)> {
+        let mut parts = pattern.splitn(2, ':');
+        let family = parts.next().unwrap_or_default().trim().to_lowercase();
+        let style = parts.next().unwrap_or_default().to_lowercase();
+        let mut weight = 400;
+        let mut italic = false;
+        for word in style.split(|c: char| c.is_whitespace() || c == ',') {
+            match word {
+                "" => {}
+                "thin" => weight = 100,
+                "extralight" | "ultralight" => weight = 200,
+                "light" => weight = 300,
+                "regular" | "normal" | "book" => weight = 400,
+                "medium" => weight = 500,
+                "semibold" | "demibold" => weight = 600,
+                "bold" => weight = 700,
+                "extrabold" | "ultrabold" => weight = 800,
+                "black" | "heavy" => weight = 900,
+                "italic" | "oblique" => italic = true,
+                _ => return Err(anyhow!("unknown font style {:?} in {:?}", word, pattern)),
+            }
+        }
+
+        let mut files = vec![];
+        for dir in font_dirs() {
+            font_files(&dir, &mut files);
+        }
+        // reading every font is slow, so try the files that look like the family first
+        let squashed: String = family.chars().filter(|c| c.is_alphanumeric()).collect();
+        let (likely, rest): (Vec<_>, Vec<_>) = files.into_iter().partition(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            name.to_lowercase().contains(&squashed)
+        });
+
+        for files in &[likely, rest] {
+            let mut best: Option<((u32, usize), PathBuf, u32)> = None;
+            for path in files {
+                let data = match std::fs::read(path) {
+                    Ok(data) => data,
+                    Err(_) => continue,
+                };
+                let count = ttf_parser::fonts_in_collection(&data).unwrap_or(1);
+                for index in 0..count {
+                    let face = match ttf_parser::Font::from_data(&data, index) {
+                        Some(face) => face,
+                        None => continue,
+                    };
+                    if face.family_name().map(|f| f.to_lowercase()) != Some(family.clone()) {
+                        continue;
+                    }
+                    let distance = (face.weight().to_number() as i32 - weight).unsigned_abs()
+                        + if face.is_italic() != italic { 1000 } else { 0 }
+                        // prefer normal width over condensed or expanded faces
+                        + (face.width().to_number() as i32 - 5).unsigned_abs();
+                    // then the plainest name, "Sans Bold" over "Sans Condensed Bold"
+                    let full_name = (face.names())
+                        .find(|name| name.name_id() == ttf_parser::name_id::FULL_NAME)
+                        .map_or(usize::MAX, |name| name.name().len());
+                    let distance = (distance, full_name);
+                    if best.as_ref().is_none_or(|(d, _, _)| distance < *d) {
+                        best = Some((distance, path.clone(), index));
+                    }
+                }
+            }
+            if let Some((_, path, index)) = best {
+                return Ok((path, index));
+            }
+        }
+        Err(anyhow!("no font found for {:?}", pattern))
+    }
+
+    /// What to do with labels wider than their button
+    #[derive(Debug, Default, Clone, Copy, PartialEq)]
+    pub enum Overflow {
+        Clip,
+        /// truncate with an ellipsis at the end
+        #[default]
+        End,
+        /// truncate with an ellipsis in the middle
+        Middle,
+        /// wrap onto as many lines as fit, then truncate
+        Wrap,
+    }
+
+    impl FromStr for Overflow {
+        type Err = anyhow::Error;
+        fn from_str(s: &str) -> Result<Self> {
+            match s {
+                "clip" => Ok(Overflow::Clip),
+                "end" => Ok(Overflow::End),
+                "middle" => Ok(Overflow::Middle),
+                "wrap" => Ok(Overflow::Wrap),
+                _ => Err(anyhow!(
+                    "overflow must be one of 'clip', 'end', 'middle', or 'wrap'"
+                )),
+            }
+        }
+    }
+
+    impl<'f> Glyphs<'f> {
+        pub fn render(self, mut d: impl FnMut(usize, usize, u8)) {
+            let (width, height) = (self.width.ceil(), self.height.ceil());
+
+            self.glyphs
+                .iter()
+                .filter_map(|g| g.pixel_bounding_box().map(|bb| (g, bb)))
+                .for_each(|(g, bb)| {
+                    g.draw(|x, y, v| {
+                        let v = (v * 255.0).ceil() as u8;
+                        let x = x as i32 + bb.min.x;
+                        let y = y as i32 + bb.min.y;
+                        if x >= 0 && x < width as i32 && y >= 0 && y < height as i32 {
+                            d(x as usize, y as usize, v);
+                        }
+                    })
+                })
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn ellipsized_labels_fit_their_width() {
+            let font = Font::embedded(DEFAULT_SIZE);
+            let label = "a label far too long for its button";
+            let width = font.width("a label far");
+            let end = font.ellipsize_end(label, width);
+            assert!(font.width(&end) <= width, "{}", end);
+            assert!(
+                end.starts_with("a label") && end.ends_with(font.ellipsis()),
+                "{}",
+                end
+            );
+            let middle = font.ellipsize_middle(label, width);
+            assert!(font.width(&middle) <= width, "{}", middle);
+            assert!(
+                middle.starts_with("a la") && middle.ends_with("ton"),
+                "{}",
+                middle
+            );
+            assert!(middle.contains(font.ellipsis()), "{}", middle);
+        }
+
+        #[test]
+        fn wrapped_lines_fit_their_width() {
+            let font = Font::embedded(DEFAULT_SIZE);
+            let width = font.width("wrap these");
+            let lines = font.wrap("wrap these words, and an unbreakable_one", width);
+            for line in lines.iter() {
+                assert!(font.width(line) <= width, "{:?}", lines);
+            }
+            assert_eq!(lines[..3], ["wrap these", "words, and", "an"]);
+            assert_eq!(lines[3..].concat(), "unbreakable_one");
+        }
+
+        #[test]
+        fn fit_ellipsizes_what_does_not_fit_in_its_lines() {
+            let font = Font::embedded(DEFAULT_SIZE);
+            let width = font.width("wrap these");
+            let two_lines = (width, 2.5 * DEFAULT_SIZE);
+            let lines = font.fit("wrap these words, and more", two_lines, Overflow::Wrap);
+            assert_eq!(lines.len(), 2);
+            assert_eq!(lines[0], "wrap these");
+            assert!(lines[1].ends_with(font.ellipsis()), "{:?}", lines);
+            assert!(font.width(&lines[1]) <= width, "{:?}", lines);
+            let fits = font.fit("short", two_lines, Overflow::End);
+            assert_eq!(fits, ["short"]);
+        }
+    }
+}
+
+use kbd::Keymap;
+mod kbd {
+    use anyhow::{anyhow, Result};
+    use std::os::unix::io::RawFd;
+    use xkbcommon_dl::{
+        xkb_context, xkb_context_flags, xkb_keymap, xkb_keymap_compile_flags, xkb_keymap_format,
+        xkb_state, xkbcommon_option, XkbCommon,
+    };
+
+    pub use xkbcommon_dl::keysyms;
+
+    /// An xkb keymap and state, compiled from the keymap a wl_keyboard sends us.
+    /// libxkbcommon is loaded at runtime, so a missing library only costs us the keyboard.
+    #[derive(Debug)]
+    pub struct Keymap {
+        context: *mut xkb_context,
+        keymap: *mut xkb_keymap,
+        state: *mut xkb_state,
+    }
+
+    fn xkb() -> &'static XkbCommon {
+        // Keymap can only be constructed if this succeeded once
+        xkbcommon_option().expect("libxkbcommon went away")
+    }
+
+    impl Keymap {
+        /// Compile the XKB_V1 keymap in `fd`, taking ownership of `fd`.
+        pub fn from_fd(fd: RawFd, size: usize) -> Result<Keymap> {
+            let result = Keymap::from_fd_inner(fd, size);
+            let _ = nix::unistd::close(fd);
+            result
+        }
+
+        fn from_fd_inner(fd: RawFd, size: usize) -> Result<Keymap> {
+            let xkb = xkbcommon_option().ok_or_else(|| anyhow!("failed to load libxkbcommon"))?;
+            let addr = unsafe {
+                libc::mmap(
+                    std::ptr::null_mut(),
+                    size,
+                    libc::PROT_READ,
+                    libc::MAP_PRIVATE,
+                    fd,
+                    0,
+                )
+            };
+            if addr == libc::MAP_FAILED || addr.is_null() {
+                return Err(anyhow!("failed to mmap keymap"));
+            }
+
+            // the keymap is a nul-terminated string
+            let (context, keymap) = unsafe {
+                let context = (xkb.xkb_context_new)(xkb_context_flags::XKB_CONTEXT_NO_FLAGS);
+                let keymap = if context.is_null() {
+                    std::ptr::null_mut()
+                } else {
+                    (xkb.xkb_keymap_new_from_string)(
+                        context,
+                        addr as *const _,
+                        xkb_keymap_format::XKB_KEYMAP_FORMAT_TEXT_V1,
+                        xkb_keymap_compile_flags::XKB_KEYMAP_COMPILE_NO_FLAGS,
+                    )
+                };
+                libc::munmap(addr, size);
+                (context, keymap)
+            };
+            if keymap.is_null() {
+                if !context.is_null() {
+                    unsafe { (xkb.xkb_context_unref)(context) };
+                }
+                return Err(anyhow!("failed to compile keymap"));
+            }
+
+            let state = unsafe { (xkb.xkb_state_new)(keymap) };
+            if state.is_null() {
+                unsafe {
+                    (xkb.xkb_keymap_unref)(keymap);
+                    (xkb.xkb_context_unref)(context);
+                }
+                return Err(anyhow!("failed to create keyboard state"));
+            }
+
+            Ok(Keymap {
+                context,
+                keymap,
+                state,
+            })
+        }
+
+        pub fn update_mask(&mut self, depressed: u32, latched: u32, locked: u32, group: u32) {
+            unsafe {
+                (xkb().xkb_state_update_mask)(self.state, depressed, latched, locked, 0, 0, group)
+            };
+        }
+
+        /// The keysym for a wl_keyboard key code, with the current modifiers applied.
+        pub fn keysym(&self, key: u32) -> u32 {
+            // wayland key codes are evdev codes, xkb key codes are offset by 8
+            unsafe { (xkb().xkb_state_key_get_one_sym)(self.state, key + 8) }
+        }
+
+        /// The text typed by a wl_keyboard key code, if any.
+        pub fn utf8(&self, key: u32) -> String {
+            let mut buf = [0u8; 64];
+            let len = unsafe {
+                (xkb().xkb_state_key_get_utf8)(
+                    self.state,
+                    key + 8,
+                    buf.as_mut_ptr() as *mut _,
+                    buf.len(),
+                )
+            };
+            let len = std::cmp::min(std::cmp::max(len, 0) as usize, buf.len() - 1);
+            String::from_utf8_lossy(&buf[..len]).into_owned()
+        }
+    }
+
+    impl Drop for Keymap {
+        fn drop(&mut self) {
+            let xkb = xkb();
+            unsafe {
+                (xkb.xkb_state_unref)(self.state);
+                (xkb.xkb_keymap_unref)(self.keymap);
+                (xkb.xkb_context_unref)(self.context);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Registry {
+    compositor: Main<WlCompositor>,
+    seats: Vec<Seat>,
+    outputs: Vec<Output>,
+    shm: Main<WlShm>,
+    /// only needed when there is no layer-shell
+    wmbase: Option<Main<XdgWmBase>>,
+    /// missing on compositors like mutter, where menus open as toplevels instead
+    layer_shell: Option<Main<LayerShell>>,
+    /// with a viewporter, fractional scales replace the outputs' integer ones
+    fractional_scale: Option<Main<FractionalScaleManager>>,
+    viewporter: Option<Main<Viewporter>>,
+    /// names outputs, for choosing one by name
+    xdg_output: Option<Main<XdgOutputManager>>,
+}
+
+#[derive(Debug)]
+struct Output {
+    name: u32,
+    wl: Main<WlOutput>,
+    scale: i32,
+    /// like "DP-1", from xdg-output
+    connector: Option<String>,
+}
+
+impl Output {
+    fn new(name: u32, wl: Main<WlOutput>) -> Output {
+        // init_registry's roundtrip may still be dispatching with just the devices
+        wl.quick_assign(move |_, ev, mut ddata| match ev {
+            wl_output::Event::Scale { factor } => {
+                if let Some(data) = ddata.get::<Data>() {
+                    data.output(name).into_iter().for_each(|o| o.scale = factor);
+                } else if let Some((_, outputs)) = ddata.get::<(Vec<Seat>, Vec<Output>)>() {
+                    (outputs.iter_mut().filter(|o| o.name == name)).for_each(|o| o.scale = factor);
+                }
+            }
+            wl_output::Event::Done => {
+                if let Some(data) = ddata.get::<Data>() {
+                    data.rescale();
+                }
+            }
+            _ => {}
+        });
+        Output {
+            name,
+            wl,
+            scale: 1,
+            connector: None,
+        }
+    }
+
+    /// Ask xdg-output for the output's name, which only version 2 and up sends
+    fn describe(&self, manager: &Main<XdgOutputManager>) {
+        let xdg = manager.get_xdg_output(&self.wl);
+        let name = self.name;
+        filter!(xdg, data,
+            zxdg_output_v1::Event::Name { name: connector } => {
+                if let Some(output) = data.output(name) {
+                    output.connector = Some(connector);
+                }
+            }
+        );
+    }
+}
+
+#[derive(Debug, Default)]
+struct Pointer {
+    wl: Option<Main<WlPointer>>,
+    /// which of the menu's surfaces the pointer is over
+    surface: Option<WlSurface>,
+    pos: Option<(f64, f64)>,
+    pos_prev: Option<(f64, f64)>,
+    btn: Option<wl_pointer::ButtonState>,
+    btn_prev: Option<wl_pointer::ButtonState>,
+    hover: Option<usize>,
+    frame: bool,
+}
+
+#[derive(Debug)]
+struct TouchPoint {
+    id: i32,
+    /// which of the menu's surfaces the point went down on
+    surface: WlSurface,
+    pos: (f64, f64),
+    /// the button this point went down on
+    start: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+struct Touch {
+    wl: Option<Main<WlTouch>>,
+    points: Vec<TouchPoint>,
+    /// the only point that can select, cleared if a second point goes down
+    primary: Option<i32>,
+    frame: bool,
+}
+
+impl Pointer {
+    /// The button pressed on `sheet`, which must be that of the surface the pointer is over
+    fn pressed(&self, sheet: &Sheet) -> Option<usize> {
+        (self.btn)
+            .filter(|s| s == &wl_pointer::ButtonState::Pressed)
+            .and(self.pos)
+            .and_then(|(x, y)| sheet.in_button(x.ceil() as usize, y.ceil() as usize))
+    }
+}
+
+impl Touch {
+    /// The button on `surface` held by the primary point, if it is still inside the button
+    /// it started on
+    fn pressed(&self, surface: &Surface) -> Option<usize> {
+        let p = self.points.iter().find(|p| Some(p.id) == self.primary)?;
+        if !p.surface.as_ref().equals(surface.wl.as_ref()) {
+            return None;
+        }
+        let (x, y) = p.pos;
+        (surface.sheet)
+            .in_button(x.ceil() as usize, y.ceil() as usize)
+            .filter(|&i| Some(i) == p.start)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Keyboard {
+    wl: Option<Main<WlKeyboard>>,
+    keymap: Option<Keymap>,
+    focus: Option<usize>,
+}
+
+#[derive(Debug)]
+struct Seat {
+    /// the registry name of the wl_seat global
+    name: u32,
+    wl: Main<WlSeat>,
+    ptr: Pointer,
+    kbd: Keyboard,
+    touch: Touch,
+}
+
+impl Seat {
+    fn new(name: u32, wl: Main<WlSeat>) -> Seat {
+        // init_registry's roundtrip may still be dispatching with just the devices
+        wl.quick_assign(move |_, ev, mut ddata| {
+            if let wl_seat::Event::Capabilities { capabilities } = ev {
+                if let Some(data) = ddata.get::<Data>() {
+                    if let Some(seat) = data.seat(name) {
+                        seat.set_capabilities(capabilities);
+                    }
+                    data.render();
+                } else if let Some((seats, _)) = ddata.get::<(Vec<Seat>, Vec<Output>)>() {
+                    (seats.iter_mut().filter(|s| s.name == name))
+                        .for_each(|s| s.set_capabilities(capabilities));
+                }
+            }
+        });
+        Seat {
+            name,
+            wl,
+            ptr: Pointer::default(),
+            kbd: Keyboard::default(),
+            touch: Touch::default(),
+        }
+    }
+
+    /// Create or destroy input devices to match the seat's capabilities
+    fn set_capabilities(&mut self, caps: wl_seat::Capability) {
+        let name = self.name;
+        match (caps.contains(wl_seat::Capability::Pointer), &self.ptr.wl) {
+            (true, None) => {
+                let ptr = Seat::create_pointer(name, &self.wl);
+                // before version 5 there are no frame events, each event stands alone
+                self.ptr.frame = ptr.as_ref().version() < 5;
+                self.ptr.wl = Some(ptr);
+            }
+            (false, Some(_)) => {
+                if let Some(ptr) = self.ptr.wl.take() {
+                    self.release(|| ptr.release());
+                }
+                self.ptr = Pointer::default();
+            }
+            _ => {}
+        }
+        match (caps.contains(wl_seat::Capability::Keyboard), &self.kbd.wl) {
+            (true, None) => self.kbd.wl = Some(Seat::create_keyboard(name, &self.wl)),
+            (false, Some(_)) => {
+                if let Some(kbd) = self.kbd.wl.take() {
+                    self.release(|| kbd.release());
+                }
+                self.kbd = Keyboard::default();
+            }
+            _ => {}
+        }
+        match (caps.contains(wl_seat::Capability::Touch), &self.touch.wl) {
+            (true, None) => self.touch.wl = Some(Seat::create_touch(name, &self.wl)),
+            (false, Some(_)) => {
+                if let Some(touch) = self.touch.wl.take() {
+                    self.release(|| touch.release());
+                }
+                self.touch = Touch::default();
+            }
+            _ => {}
+        }
+    }
+
+    /// Input device release requests only exist since wl_seat version 3
+    fn release(&self, release: impl FnOnce()) {
+        if self.wl.as_ref().version() >= 3 {
+            release();
+        }
+    }
+
+    fn create_pointer(name: u32, seat: &Main<WlSeat>) -> Main<WlPointer> {
+        let pointer = seat.get_pointer();
+        filter!(pointer, data,
+            wl_pointer::Event::Enter { surface, surface_x, surface_y, .. } => {
+                if let Some(seat) = data.seat(name) {
+                    seat.ptr.surface.replace(surface);
+                    seat.ptr.pos.replace((surface_x, surface_y));
+                }
+            },
+            wl_pointer::Event::Leave { .. } => {
+                if let Some(seat) = data.seat(name) {
+                    seat.ptr.surface.take();
+                    seat.ptr.pos.take();
+                    seat.ptr.btn.take();
+                }
+            },
+            wl_pointer::Event::Motion { surface_x, surface_y, .. } => {
+                if let Some(seat) = data.seat(name) {
+                    seat.ptr.pos.replace((surface_x, surface_y));
+                }
+            },
+            wl_pointer::Event::Button { button: 0x110, state, .. } => {
+                // 0x110 is BUTTON1
+                if let Some(seat) = data.seat(name) {
+                    seat.ptr.btn.replace(state);
+                }
+            },
+            wl_pointer::Event::Frame => {
+                if let Some(seat) = data.seat(name) {
+                    seat.ptr.frame = true;
+                }
+            }
+        );
+        pointer
+    }
+
+    fn create_keyboard(name: u32, seat: &Main<WlSeat>) -> Main<WlKeyboard> {
+        let keyboard = seat.get_keyboard();
+        filter!(keyboard, data,
+            wl_keyboard::Event::Keymap { format, fd, size } => {
+                let seat = match data.seat(name) {
+                    Some(seat) => seat,
+                    None => {
+                        let _ = nix::unistd::close(fd);
+                        return;
+                    }
+                };
+                seat.kbd.keymap = None;
+                if format != wl_keyboard::KeymapFormat::XkbV1 {
+                    let _ = nix::unistd::close(fd);
+                    eprintln!("unsupported keymap format {:?}", format);
+                    return;
+                }
+                match Keymap::from_fd(fd, size as usize) {
+                    Ok(keymap) => seat.kbd.keymap = Some(keymap),
+                    Err(err) => eprintln!("failed to load keymap: {}", err),
+                }
+            },
+            wl_keyboard::Event::Modifiers {
+                mods_depressed, mods_latched, mods_locked, group, ..
+            } => {
+                if let Some(keymap) = data.seat(name).and_then(|seat| seat.kbd.keymap.as_mut()) {
+                    keymap.update_mask(mods_depressed, mods_latched, mods_locked, group);
+                }
+            },
+            wl_keyboard::Event::Key { key, state: wl_keyboard::KeyState::Pressed, .. } => {
+                if let Some((sym, text)) = (data.seat(name))
+                    .and_then(|seat| seat.kbd.keymap.as_ref())
+                    .map(|k| (k.keysym(key), k.utf8(key)))
+                {
+                    data.key_press(name, sym, &text);
+                }
+            }
+        );
+        keyboard
+    }
+
+    fn create_touch(name: u32, seat: &Main<WlSeat>) -> Main<WlTouch> {
+        let touch = seat.get_touch();
+        filter!(touch, data,
+            wl_touch::Event::Down { surface, id, x, y, .. } => {
+                let start = (data.surface(&surface))
+                    .and_then(|s| s.sheet.in_button(x.ceil() as usize, y.ceil() as usize));
+                if let Some(seat) = data.seat(name) {
+                    let touch = &mut seat.touch;
+                    touch.primary = if touch.points.is_empty() { Some(id) } else { None };
+                    touch.points.push(TouchPoint { id, surface, pos: (x, y), start });
+                }
+            },
+            wl_touch::Event::Motion { id, x, y, .. } => {
+                if let Some(p) = (data.seat(name))
+                    .and_then(|seat| seat.touch.points.iter_mut().find(|p| p.id == id))
+                {
+                    p.pos = (x, y);
+                }
+            },
+            wl_touch::Event::Up { id, .. } => data.touch_up(name, id),
+            wl_touch::Event::Cancel => {
+                if let Some(seat) = data.seat(name) {
+                    seat.touch.points.clear();
+                    seat.touch.primary = None;
+                    seat.touch.frame = true;
+                }
+            },
+            wl_touch::Event::Frame => {
+                if let Some(seat) = data.seat(name) {
+                    seat.touch.frame = true;
+                }
+            }
+        );
+        touch
+    }
+}
+
+/// One on screen, one being drawn
+const BUFFERS: usize = 2;
+
+#[derive(Debug)]
+struct Surface {
+    wl: Main<WlSurface>,
+    role: Role,
+    /// the menu laid out at the size the compositor configured for this surface
+    sheet: Sheet,
+    buffers: BufferPool,
+    /// the menu changed since the last frame
+    dirty: bool,
+    /// waiting on the compositor to ask for the next frame
+    throttled: bool,
+    configured: bool,
+    /// names of the outputs the surface is on
+    outputs: Vec<u32>,
+    /// buffer pixels per logical pixel
+    scale: f64,
+    /// set when the compositor sends fractional scales, sized to the logical size
+    viewport: Option<Main<Viewport>>,
+}
+
+#[derive(Debug)]
+enum Role {
+    Layer(Main<LayerSurface>),
+    /// the xdg_toplevel lives on in its filter, only its xdg_surface is acked
+    Toplevel(Main<XdgSurface>),
+}
+
+#[derive(Debug)]
+struct Data {
+    cfg: Config,
+    registry: Registry,
+    selected: Option<usize>,
+    shm_formats: Vec<wl_shm::Format>,
+    /// one per output the menu is shown on
+    surfaces: Vec<Surface>,
+}
+
+impl Data {
+    fn new(cfg: Config, mut registry: Registry) -> Data {
+        if let Some(wmbase) = &mut registry.wmbase {
+            filter!(wmbase, data,
+                xdg_wm_base::Event::Ping { serial } => {
+                    if let Some(wmbase) = &data.registry.wmbase {
+                        wmbase.detach().pong(serial);
+                    }
+                }
+            );
+        }
+
+        let shm = &mut registry.shm;
+        filter!(shm, data,
+            wl_shm::Event::Format { format } => data.shm_formats.push(format)
+        );
+
+        if let Some(manager) = &registry.xdg_output {
+            for output in registry.outputs.iter() {
+                output.describe(manager);
+            }
+        }
+
+        Data {
+            cfg,
+            registry,
+            selected: None,
+            surfaces: vec![],
+            shm_formats: vec![],
+        }
+    }
+
+    /// Create the menu's surfaces on the outputs `--output` chose, once they are named
+    fn show(&mut self) -> Result<()> {
+        let outputs = &self.registry.outputs;
+        let chosen = match (&self.cfg.placement.output, &self.registry.layer_shell) {
+            // toplevels go wherever the compositor puts them
+            (OutputChoice::Focused, _) | (_, None) => vec![None],
+            (OutputChoice::All, _) if outputs.is_empty() => Err(anyhow!("there are no outputs"))?,
+            (OutputChoice::All, _) => outputs.iter().map(|o| Some(o.wl.detach())).collect(),
+            (OutputChoice::Index(i), _) => match outputs.get(*i) {
+                Some(output) => vec![Some(output.wl.detach())],
+                None => Err(anyhow!("there is no output {}, only {}", i, outputs.len()))?,
+            },
+            (OutputChoice::Name(name), _) => {
+                match outputs.iter().find(|o| o.connector.as_ref() == Some(name)) {
+                    Some(output) => vec![Some(output.wl.detach())],
+                    None => {
+                        let names: Vec<_> = outputs.iter().flat_map(|o| &o.connector).collect();
+                        Err(anyhow!("there is no output {:?}, only {:?}", name, names))?
+                    }
+                }
+            }
+        };
+        for output in chosen {
+            let surface = Data::create_surface(&self.cfg, &self.registry, output.as_ref())?;
+            self.surfaces.push(surface);
+        }
+        self.render();
+        Ok(())
+    }
+
+    fn output(&mut self, name: u32) -> Option<&mut Output> {
+        self.registry.outputs.iter_mut().find(|o| o.name == name)
+    }
+
+    fn surface(&mut self, wl: &WlSurface) -> Option<&mut Surface> {
+        (self.surfaces.iter_mut()).find(|s| s.wl.as_ref().equals(wl.as_ref()))
+    }
+
+    /// The compositor took one of our surfaces away, which is only the end if it was the last
+    fn remove_surface(&mut self, wl: &WlSurface) {
+        self.surfaces.retain(|s| !s.wl.as_ref().equals(wl.as_ref()));
+        if self.surfaces.is_empty() {
+            self.cfg.exit = Some(Exit::Closed);
+        }
+    }
+
+    fn seat(&mut self, name: u32) -> Option<&mut Seat> {
+        self.registry
+            .seats
+            .iter_mut()
+            .find(|seat| seat.name == name)
+    }
+
+    fn remove_seat(&mut self, name: u32) {
+        if let Some(i) = self
+            .registry
+            .seats
+            .iter()
+            .position(|seat| seat.name == name)
+        {
+            let mut seat = self.registry.seats.remove(i);
+            seat.set_capabilities(wl_seat::Capability::empty());
+            if seat.wl.as_ref().version() >= 5 {
+                seat.wl.release();
+            }
+            self.render();
+        }
+    }
+
+    fn remove_output(&mut self, name: u32) {
+        if let Some(i) = self.registry.outputs.iter().position(|o| o.name == name) {
+            let output = self.registry.outputs.remove(i);
+            if output.wl.as_ref().version() >= 3 {
+                output.wl.release();
+            }
+        }
+        for surface in self.surfaces.iter_mut() {
+            surface.outputs.retain(|&o| o != name);
+        }
+        self.rescale();
+    }
+
+    /// A touch point lifting inside the button it went down on selects that button
+    fn touch_up(&mut self, name: u32, id: i32) {
+        let (cfg, surfaces) = (&self.cfg, &self.surfaces);
+        let touch = match self
+            .registry
+            .seats
+            .iter_mut()
+            .find(|seat| seat.name == name)
+        {
+            Some(seat) => &mut seat.touch,
+            None => return,
+        };
+        if Some(id) == touch.primary {
+            let pressed = (touch.points.iter().find(|p| p.id == id))
+                .and_then(|p| {
+                    surfaces
+                        .iter()
+                        .find(|s| s.wl.as_ref().equals(p.surface.as_ref()))
+                })
+                .and_then(|surface| touch.pressed(surface));
+            self.selected = (self.selected).or_else(|| pressed.and_then(|i| cfg.option(i)));
+            touch.primary = None;
+        }
+        touch.points.retain(|p| p.id != id);
+    }
+
+    /// Act on the pointer and touch frames that arrived during the last dispatch
+    fn process_input(&mut self) {
+        let (cfg, surfaces) = (&self.cfg, &self.surfaces);
+        let mut render = false;
+        for seat in self.registry.seats.iter_mut() {
+            let ptr = &mut seat.ptr;
+            let sheet = (ptr.surface.as_ref())
+                .and_then(|wl| surfaces.iter().find(|s| s.wl.as_ref().equals(wl.as_ref())))
+                .map(|surface| &surface.sheet);
+            let in_button = |(x, y): (f64, f64)| {
+                sheet.and_then(|sheet| sheet.in_button(x.ceil() as usize, y.ceil() as usize))
+            };
+            let hover = ptr.pos.and_then(in_button);
+            if ptr.frame
+                && (ptr.pos_prev.is_some() ^ ptr.pos.is_some()
+                    || ptr.btn != ptr.btn_prev
+                    || ptr.hover != hover)
+            {
+                ptr.btn_prev = ptr.btn;
+                ptr.pos_prev = ptr.pos;
+                ptr.hover = hover;
+                render = true;
+
+                self.selected = self.selected.or_else(|| {
+                    (ptr.btn)
+                        .filter(|btn| btn == &wl_pointer::ButtonState::Released)
+                        .and(ptr.pos)
+                        .and_then(in_button)
+                        .and_then(|i| cfg.option(i))
+                });
+            }
+
+            if seat.touch.frame {
+                seat.touch.frame = false;
+                render = true;
+            }
+        }
+
+        if render {
+            self.render();
+        }
+    }
+
+    /// A layer surface over everything else if the compositor has layer-shell,
+    /// otherwise a plain toplevel window
+    fn create_surface(
+        cfg: &Config,
+        registry: &Registry,
+        output: Option<&WlOutput>,
+    ) -> Result<Surface> {
+        let wl = registry.compositor.create_surface();
+        let sheet = cfg.arrange((0, 0));
+        let (width, height) = render::scaled(sheet.bounds, 1.0)?;
+        let buffers = create_buffer_pool(width, height, BUFFERS, &registry.shm)?;
+        let (width, height) = (width as i32, height as i32);
+        let role = match (&registry.layer_shell, &registry.wmbase) {
+            (Some(layer_shell), _) => {
+                Data::create_layer(&wl, (width, height), layer_shell, &cfg.placement, output)
+            }
+            (None, Some(wmbase)) => {
+                Data::create_toplevel(&wl, width, height, wmbase, &cfg.placement)
+            }
+            (None, None) => unreachable!("init_registry requires one shell or the other"),
+        };
+        let surface = wl.detach();
+        filter!(wl, data,
+            wl_surface::Event::Enter { output } => {
+                let entered = (data.registry.outputs.iter())
+                    .find(|o| o.wl.as_ref().equals(output.as_ref()))
+                    .map(|o| o.name);
+                if let Some(surface) = data.surface(&surface) {
+                    surface.outputs.extend(entered);
+                }
+                data.rescale();
             },
-            wl_pointer::Event::Frame => {
-                data.ptr.frame = true;
+            wl_surface::Event::Leave { output } => {
+                let left = (data.registry.outputs.iter())
+                    .find(|o| o.wl.as_ref().equals(output.as_ref()))
+                    .map(|o| o.name);
+                if let Some(surface) = data.surface(&surface) {
+                    surface.outputs.retain(|&name| Some(name) != left);
+                }
+                data.rescale();
             }
         );
+        let viewport = match (&registry.fractional_scale, &registry.viewporter) {
+            (Some(manager), Some(viewporter)) => {
+                let fractional = manager.get_fractional_scale(&wl);
+                let surface = wl.detach();
+                // its only event, which filter! would warn has an unreachable catch-all
+                fractional.quick_assign(move |_, ev, mut ddata| {
+                    let fractional_scale::Event::PreferredScale { scale } = ev;
+                    if let Some(data) = ddata.get::<Data>() {
+                        // in 120ths
+                        data.set_scale(&surface, scale as f64 / 120.0);
+                    }
+                });
+                let viewport = viewporter.get_viewport(&wl);
+                viewport.set_destination(width, height);
+                Some(viewport)
+            }
+            _ => None,
+        };
+        wl.commit();
 
-        let wmbase = &mut registry.wmbase;
-        filter!(wmbase, data,
-            xdg_wm_base::Event::Ping { serial } => data.registry.wmbase.detach().pong(serial)
-        );
+        Ok(Surface {
+            wl,
+            role,
+            sheet,
+            buffers,
+            dirty: true,
+            throttled: false,
+            configured: false,
+            outputs: vec![],
+            scale: 1.0,
+            viewport,
+        })
+    }
 
-        let shm = &mut registry.shm;
-        filter!(shm, data,
-            wl_shm::Event::Format { format } => data.shm_formats.push(format)
-        );
+    /// Follow the largest integer scale of the outputs each surface is on,
+    /// unless the compositor sends fractional scales instead
+    fn rescale(&mut self) {
+        let outputs = &self.registry.outputs;
+        let scales: Vec<_> = (self.surfaces.iter())
+            // buffer scales need wl_surface version 3
+            .filter(|s| s.viewport.is_none() && s.wl.as_ref().version() >= 3)
+            .map(|s| {
+                let scale = (s.outputs.iter())
+                    .filter_map(|&name| outputs.iter().find(|o| o.name == name))
+                    .map(|o| o.scale)
+                    .max()
+                    .unwrap_or(1);
+                (s.wl.detach(), scale)
+            })
+            .collect();
+        for (surface, scale) in scales {
+            self.set_scale(&surface, scale as f64);
+        }
+    }
 
-        let (width, height) = cfg.buttons_bounds();
-        let shmbuffer = create_shmbuffer(width, height, shm).expect("failed to create shm");
+    /// Render `surface` `scale` times the logical size
+    fn set_scale(&mut self, surface: &WlSurface, scale: f64) {
+        let surface =
+            match (self.surfaces.iter_mut()).find(|s| s.wl.as_ref().equals(surface.as_ref())) {
+                Some(surface) if surface.scale != scale => surface,
+                _ => return,
+            };
+        surface.scale = scale;
+        if surface.viewport.is_none() {
+            surface.wl.set_buffer_scale(scale as i32);
+        }
+        if let Err(err) = Data::reallocate(surface, &self.registry.shm) {
+            self.fail(err);
+        }
+    }
 
-        let (width, height) = cfg.buttons_bounds();
+    /// Lay `surface` out again for the size the compositor configured for it,
+    /// which is 0 where it leaves the size to us
+    fn resize(&mut self, surface: &WlSurface, width: u32, height: u32) {
+        let size = (width as usize, height as usize);
         let surface =
-            Data::create_surface(width, height, &registry.compositor, &registry.layer_shell);
+            match (self.surfaces.iter_mut()).find(|s| s.wl.as_ref().equals(surface.as_ref())) {
+                Some(surface) if surface.sheet.size != size => surface,
+                _ => return,
+            };
+        surface.sheet = self.cfg.arrange(size);
+        if let Err(err) = Data::reallocate(surface, &self.registry.shm) {
+            self.fail(err);
+        }
+    }
 
-        let mut data = Data {
-            cfg,
-            registry,
-            ptr: Pointer::default(),
-            buffer: shmbuffer,
-            surface: surface,
-            seat_cap: wl_seat::Capability::from_raw(0).unwrap(),
-            shm_formats: vec![],
-            rendered: false,
-        };
-        data.render();
-        data
+    /// Give `surface` a buffer of its sheet's size times its scale, then redraw it
+    fn reallocate(surface: &mut Surface, shm: &Main<WlShm>) -> Result<()> {
+        let bounds = surface.sheet.bounds;
+        let (width, height) = render::scaled(bounds, surface.scale)?;
+        if (surface.buffers.width, surface.buffers.height) != (width, height) {
+            surface.buffers = create_buffer_pool(width, height, BUFFERS, shm)
+                .context("failed to resize the menu's buffers")?;
+        }
+        if let Some(viewport) = &surface.viewport {
+            viewport.set_destination(bounds.0 as i32, bounds.1 as i32);
+        }
+        surface.dirty = true;
+        Ok(())
     }
 
-    fn create_surface(
-        width: usize,
-        height: usize,
-        compositor: &Main<WlCompositor>,
+    /// End the menu with `err`, from an event filter that can't return it to `run`
+    fn fail(&mut self, err: anyhow::Error) {
+        eprintln!("Error: {:?}", err);
+        self.cfg.exit = Some(Exit::Error);
+    }
+
+    fn create_layer(
+        wl: &WlSurface,
+        (width, height): (i32, i32),
         layer_shell: &Main<LayerShell>,
-    ) -> Surface {
-        let wl = compositor.create_surface();
-        let (width, height) = (width as i32, height as i32);
-        let namespace = String::from("wtmenu");
-        let layer = layer_shell.get_layer_surface(&wl.detach(), None, Layer::Overlay, namespace);
-        layer.set_size(width as u32, height as u32);
+        placement: &Placement,
+        output: Option<&WlOutput>,
+    ) -> Role {
+        let namespace = placement.namespace.clone();
+        let layer = layer_shell.get_layer_surface(wl, output, placement.layer, namespace);
+        // anchored to opposite edges, the compositor stretches the menu between them
+        let anchored = |edges| placement.anchor.contains(edges);
+        let width = match anchored(layer_surface::Anchor::Left | layer_surface::Anchor::Right) {
+            true => 0,
+            false => width as u32,
+        };
+        let height = match anchored(layer_surface::Anchor::Top | layer_surface::Anchor::Bottom) {
+            true => 0,
+            false => height as u32,
+        };
+        layer.set_size(width, height);
+        layer.set_anchor(placement.anchor);
+        let (top, right, bottom, left) = placement.margin;
+        layer.set_margin(top, right, bottom, left);
+        layer.set_exclusive_zone(placement.exclusive_zone);
+        layer.set_keyboard_interactivity(layer_surface::KeyboardInteractivity::Exclusive);
+        let surface = wl.clone();
         filter!(layer, data,
-            layer_surface::Event::Configure { serial, .. } => {
-                data.surface.layer.detach().ack_configure(serial);
-                data.surface.configured = true;
+            layer_surface::Event::Configure { serial, width, height } => {
+                if let Some(surface) = data.surface(&surface) {
+                    if let Role::Layer(layer) = &surface.role {
+                        layer.detach().ack_configure(serial);
+                    }
+                    surface.configured = true;
+                    // the ack goes out with the next commit
+                    surface.dirty = true;
+                }
+                data.resize(&surface, width, height);
+            },
+            layer_surface::Event::Closed => data.remove_surface(&surface)
+        );
+        Role::Layer(layer)
+    }
+
+    fn create_toplevel(
+        wl: &WlSurface,
+        width: i32,
+        height: i32,
+        wmbase: &Main<XdgWmBase>,
+        placement: &Placement,
+    ) -> Role {
+        let xdg = wmbase.get_xdg_surface(wl);
+        let surface = wl.clone();
+        filter!(xdg, data,
+            xdg_surface::Event::Configure { serial } => {
+                if let Some(surface) = data.surface(&surface) {
+                    if let Role::Toplevel(xdg) = &surface.role {
+                        xdg.detach().ack_configure(serial);
+                    }
+                    surface.configured = true;
+                    // the ack goes out with the next commit
+                    surface.dirty = true;
+                }
+            }
+        );
+        let toplevel = xdg.get_toplevel();
+        let surface = wl.clone();
+        toplevel.set_app_id(placement.namespace.clone());
+        toplevel.set_title(String::from("wtmenu"));
+        // a fixed size hints tiling compositors to float the menu
+        toplevel.set_min_size(width, height);
+        toplevel.set_max_size(width, height);
+        filter!(toplevel, data,
+            // applied before the xdg_surface configure that follows is acked,
+            // but nothing is committed in between
+            xdg_toplevel::Event::Configure { width, height, .. } => {
+                data.resize(&surface, width.max(0) as u32, height.max(0) as u32);
             },
-            layer_surface::Event::Closed => {
-                data.cfg.should_close = true;
+            // the compositor asks on the user's behalf
+            xdg_toplevel::Event::Close => {
+                data.cfg.exit = Some(Exit::Cancelled);
             }
         );
-        wl.commit();
+        Role::Toplevel(xdg)
+    }
 
-        Surface {
-            wl,
-            layer,
-            committed: false,
-            configured: false,
+    fn key_press(&mut self, name: u32, sym: u32, text: &str) {
+        use kbd::keysyms;
+
+        let n = self.cfg.visible.len();
+        let (layout, total) = (self.cfg.layout, self.cfg.options.len());
+        let step = |i: Option<usize>, dir| Some(i.map_or(0, |i| layout.neighbor(total, n, i, dir)));
+        let query_len = self.cfg.query.len();
+        let kbd = match self
+            .registry
+            .seats
+            .iter_mut()
+            .find(|seat| seat.name == name)
+        {
+            Some(seat) => &mut seat.kbd,
+            None => return,
+        };
+        let focus = kbd.focus;
+        match sym {
+            keysyms::Tab if n > 0 => kbd.focus = Some(focus.map_or(0, |i| (i + 1) % n)),
+            keysyms::ISO_Left_Tab if n > 0 => {
+                kbd.focus = Some(focus.map_or(n - 1, |i| (i + n - 1) % n))
+            }
+            keysyms::Right if n > 0 => kbd.focus = step(focus, Direction::Right),
+            keysyms::Left if n > 0 => kbd.focus = step(focus, Direction::Left),
+            keysyms::Down if n > 0 => kbd.focus = step(focus, Direction::Down),
+            keysyms::Up if n > 0 => kbd.focus = step(focus, Direction::Up),
+            keysyms::Home if n > 0 => kbd.focus = Some(0),
+            keysyms::End if n > 0 => kbd.focus = Some(n - 1),
+            keysyms::Return | keysyms::KP_Enter => {
+                // with nothing focused, enter picks the top match
+                self.selected = self.cfg.option(focus.unwrap_or(0));
+            }
+            keysyms::Escape => self.cfg.exit = Some(Exit::Cancelled),
+            keysyms::BackSpace => {
+                self.cfg.query.pop();
+            }
+            _ if self.cfg.query.is_empty() && self.cfg.hotkey(text).is_some() => {
+                self.selected = self.cfg.hotkey(text);
+            }
+            _ if !text.is_empty() && !text.chars().any(char::is_control) => {
+                self.cfg.query.push_str(text);
+            }
+            _ => return,
+        }
+        if self.cfg.query.len() != query_len {
+            self.cfg.refilter();
+            for surface in self.surfaces.iter_mut() {
+                surface.sheet = self.cfg.arrange(surface.sheet.size);
+            }
+            for seat in self.registry.seats.iter_mut() {
+                seat.kbd.focus = None;
+            }
+            self.render();
+        } else if kbd.focus != focus {
+            self.render();
         }
     }
 
+    /// Redraw every surface on its next frame
     fn render(&mut self) {
-        if self.buffer.locked {
-            return;
+        for surface in self.surfaces.iter_mut() {
+            surface.dirty = true;
         }
-        let shm = &mut self.buffer;
-        let (bw, bh) = self.cfg.button_dim;
-
-        let focus = {
-            let cfg = &self.cfg;
-            (self.ptr.btn)
-                .filter(|s| s == &wl_pointer::ButtonState::Pressed)
-                .and(self.ptr.pos)
-                .and_then(|(x, y)| cfg.in_button(x.ceil() as usize, y.ceil() as usize))
-        };
+    }
 
-        for i in 0..shm.width {
-            for j in 0..shm.height {
-                if let Some(opti) = self.cfg.in_button(i, j) {
-                    shm[(i, j)] = if Some(opti) == focus {
-                        self.cfg.sb
-                    } else {
-                        self.cfg.nb
-                    };
-                } else {
-                    shm[(i, j)] = (self.cfg.nb & 0xffffff) | 0x22000000;
+    /// Draw and commit the dirty surfaces the compositor is ready for, at most
+    /// once per frame callback
+    fn frame(&mut self) {
+        let cfg = &self.cfg;
+        let ready = |s: &&mut Surface| s.configured && s.dirty && !s.throttled;
+        for surface in self.surfaces.iter_mut().filter(ready) {
+            // otherwise wait for a Release, still dirty
+            let index = match surface.buffers.free() {
+                Some(index) => index,
+                None => continue,
+            };
+            let mut state = State::default();
+            for seat in self.registry.seats.iter() {
+                // a pointer only shows on the surface it is over
+                let ptr = &seat.ptr;
+                if (ptr.surface.as_ref()).is_some_and(|s| s.as_ref().equals(surface.wl.as_ref())) {
+                    state.pressed.extend(ptr.pressed(&surface.sheet));
+                    state.hover.extend(ptr.hover);
                 }
+                state.pressed.extend(seat.touch.pressed(surface));
+                state.pressed.extend(seat.kbd.focus);
             }
+            render::draw(
+                cfg,
+                &surface.sheet,
+                &state,
+                &mut surface.buffers.canvas(index),
+                surface.scale,
+            );
+
+            let (ww, wh) = surface.sheet.bounds;
+            surface.wl.damage(0, 0, ww as i32, wh as i32);
+            let (wl, callback) = (surface.wl.detach(), surface.wl.frame());
+            filter!(callback, data,
+                wl_callback::Event::Done { .. } => {
+                    if let Some(surface) = data.surface(&wl) {
+                        surface.throttled = false;
+                    }
+                }
+            );
+            let buffer = &mut surface.buffers.buffers[index];
+            surface.wl.attach(Some(&buffer.wl), 0, 0);
+            buffer.locked = true;
+            surface.wl.commit();
+            surface.dirty = false;
+            surface.throttled = true;
         }
+    }
+}
 
-        let scale = |v: u8, s: u8| ((v as u32 * s as u32) / 255) as u8;
-        let (nf, sf) = (self.cfg.nf, self.cfg.sf);
-        let rendered = self.rendered;
-        for i in 0..self.cfg.options.len() {
-            let opt = self.cfg.options.get(i).unwrap();
-            let g = self.cfg.font.glyphs(opt);
+use render::{Snapshot, State};
+mod render {
+    use super::pixbuf::{self, Canvas, Image};
+    use super::{Config, Glyphs, Sheet};
+    use anyhow::{anyhow, Context, Result};
+    use std::cmp::{max, min};
+    use std::io::Write;
+    use std::path::PathBuf;
 
-            let (left, right, top, bottom) = self.cfg.button_bounds(i);
+    /// What input is doing to the buttons, as indices of visible buttons
+    #[derive(Debug, Default)]
+    pub struct State {
+        pub pressed: Vec<usize>,
+        pub hover: Vec<usize>,
+    }
 
-            let trans_x: i32 = max(left, left - (g.width.ceil() as i32 - bw as i32) / 2);
-            let trans_y: i32 = max(top, top - (g.height.ceil() as i32 - bh as i32) / 2);
+    /// Draw the menu as laid out on `sheet` into `canvas`, which must be at least
+    /// `sheet.bounds` times `scale` big. The layout is in logical pixels, and scaled
+    /// to the canvas' pixels.
+    pub fn draw(cfg: &Config, sheet: &Sheet, state: &State, canvas: &mut Canvas, scale: f64) {
+        let colors = |i| {
+            if state.pressed.contains(&i) {
+                (cfg.sf, cfg.sb)
+            } else if state.hover.contains(&i) {
+                (cfg.hf, cfg.hb)
+            } else {
+                (cfg.nf, cfg.nb)
+            }
+        };
+        let px = |v: i32| (v as f64 * scale).round() as i32;
+        // squeezed into a small size, buttons can stick out of the canvas
+        let (width, height) = (canvas.width as i32, canvas.height as i32);
+        let physical = |(left, right, top, bottom)| {
+            let (right, bottom) = (min(px(right), width), min(px(bottom), height));
+            (min(px(left), right), right, min(px(top), bottom), bottom)
+        };
 
-            let (mut warn_btn, mut warn_buf) = (false, false);
-            g.render(|x, y, v| {
-                let (x, y) = (x as i32 + trans_x, y as i32 + trans_y);
-                if x < 0 || x as usize >= shm.width || y < 0 || y as usize >= shm.height {
-                    if !rendered && !warn_buf {
-                        eprintln!(
-                            "glyph for {:?} exceeds buffer boundaries: {:?} {:?}",
-                            opt,
-                            (x, y),
-                            (shm.width, shm.height)
-                        );
-                        warn_buf = true;
-                    }
-                    return;
+        // the window background replaces what was there, everything else is blended over it
+        let background = pixbuf::premultiply((cfg.nb & 0xffffff) | 0x22000000);
+        for pixel in canvas.pixels.iter_mut() {
+            *pixel = background;
+        }
+        let mut fill = |(left, right, top, bottom): (i32, i32, i32, i32), color| {
+            let color = pixbuf::premultiply(color);
+            for i in left as usize..right as usize {
+                for j in top as usize..bottom as usize {
+                    canvas.blend((i, j), color);
                 }
+            }
+        };
+        fill(physical(cfg.query_bounds(sheet)), cfg.nb);
+        for i in 0..sheet.rects.len() {
+            fill(physical(sheet.button_bounds(i)), colors(i).1);
+        }
+
+        let mut text = |g: Glyphs, (x0, y0): (i32, i32), clip: (i32, i32, i32, i32), color: u32| {
+            let (left, right, top, bottom) = clip;
+            let color = pixbuf::premultiply(color);
+            g.render(|x, y, v| {
+                let (x, y) = (x as i32 + x0, y as i32 + y0);
                 if x < left || x >= right || y < top || y >= bottom {
-                    if !rendered && !warn_btn {
-                        eprintln!(
-                            "glyph for {:?} exceeds button boundaries: {:?} {:?}",
-                            opt,
-                            (x, y),
-                            (left, right, top, bottom)
-                        );
-                        warn_btn = true;
-                    }
                     return;
                 }
-
-                let pixi = (x as usize, y as usize);
-                let [a, rb, gb, bb] = shm[pixi].to_be_bytes();
-                let [_, rf, gf, bf] = if Some(i) == focus {
-                    sf.to_be_bytes()
-                } else {
-                    nf.to_be_bytes()
-                };
-                shm[pixi] = u32::from_be_bytes([
-                    a,
-                    max(rb, scale(v, rf)),
-                    max(gb, scale(v, gf)),
-                    max(bb, scale(v, bf)),
-                ]);
+                canvas.blend((x as usize, y as usize), pixbuf::coverage(color, v));
             });
+        };
+
+        // the query is left aligned and scrolls off the right edge quietly
+        let query = physical(cfg.query_bounds(sheet));
+        let origin = (query.0 + px(cfg.border as i32), query.2);
+        // until something is typed, the bar shows the tooltip of what input is on
+        let tooltip = (state.hover.iter().chain(state.pressed.iter()))
+            .filter_map(|&i| cfg.options[*cfg.visible.get(i)?].tooltip.as_ref())
+            .next()
+            .filter(|_| cfg.query.is_empty());
+        match tooltip {
+            Some(tooltip) => text(
+                cfg.font.glyphs_scaled(tooltip, scale as f32),
+                origin,
+                query,
+                faded(cfg.nf),
+            ),
+            None => text(
+                cfg.font.glyphs_scaled(&cfg.query, scale as f32),
+                origin,
+                query,
+                cfg.nf,
+            ),
+        }
+
+        let (pad, line_height) = (cfg.padding as i32, cfg.font.height() as i32);
+        for (i, &opti) in cfg.visible.iter().enumerate() {
+            // labels are fit in logical pixels, so they break the same at any scale
+            let (left, right, top, bottom) = sheet.button_bounds(i);
+            let (bw, bh) = (right - left, bottom - top);
+            let area = (max(bw - 2 * pad, 1) as f32, max(bh - 2 * pad, 1) as f32);
+            let item = &cfg.options[opti];
+            let lines = cfg.font.fit(&item.text(), area, cfg.overflow);
+            // its own color stands in for -nf, so hover and press still show
+            let normal = !state.pressed.contains(&i) && !state.hover.contains(&i);
+            let color = match item.color {
+                Some(color) if normal => color,
+                _ => colors(i).0,
+            };
+            let color = if item.disabled { faded(color) } else { color };
+
+            let bounds = physical(sheet.button_bounds(i));
+            let (left, right, top, bottom) = bounds;
+            let (bw, bh, line_height) = (right - left, bottom - top, px(line_height));
+            let trans_y = max(top, top + (bh - line_height * lines.len() as i32) / 2);
+            for (l, line) in lines.iter().enumerate() {
+                let g = cfg.font.glyphs_scaled(line, scale as f32);
+                let trans_x = max(left, left + (bw - g.width.ceil() as i32) / 2);
+                let origin = (trans_x, trans_y + l as i32 * line_height);
+                text(g, origin, bounds, color);
+            }
         }
+    }
+
+    /// `color` at half its alpha
+    fn faded(color: u32) -> u32 {
+        (color & 0xffffff) | (color >> 25) << 24
+    }
+
+    /// The most pixels in one buffer. `BUFFERS` of them must fit an shm pool,
+    /// whose size is an i32, and any more is a mistake rather than a menu.
+    const MAX_PIXELS: usize = 1 << 26;
+
+    /// The size in buffer pixels of `(width, height)` logical pixels, unless a
+    /// buffer that big is more than we are willing to allocate
+    pub fn scaled((width, height): (usize, usize), scale: f64) -> Result<(usize, usize)> {
+        let px = |v: usize| (v as f64 * scale).round() as usize;
+        let (width, height) = (px(width), px(height));
+        // an empty side still must not overflow a stride
+        match max(width, 1).checked_mul(max(height, 1)) {
+            Some(pixels) if pixels <= MAX_PIXELS => Ok((width, height)),
+            _ => Err(anyhow!(
+                "the menu would be {}x{} pixels, too big to draw",
+                width,
+                height
+            )),
+        }
+    }
+
+    /// Render the menu to a file instead of a surface, see `--render-to`
+    #[derive(Debug)]
+    pub struct Snapshot {
+        pub path: PathBuf,
+        /// where the pointer hovers, if anywhere
+        pub pointer: Option<(usize, usize)>,
+        /// the visible button with keyboard focus, drawn as pressed
+        pub focus: Option<usize>,
+        /// buffer pixels per logical pixel
+        pub scale: f64,
+        /// as if configured by the compositor, 0 where it leaves the size to us
+        pub size: (usize, usize),
+    }
 
-        let (ww, wh) = self.cfg.buttons_bounds();
-        self.surface.wl.damage(0, 0, ww as i32, wh as i32);
-        self.surface.committed = false;
-        self.rendered = true;
+    impl Snapshot {
+        pub fn write(&self, cfg: &Config) -> Result<()> {
+            let sheet = cfg.arrange(self.size);
+            let mut state = State::default();
+            if let Some(focus) = self.focus {
+                if focus >= sheet.rects.len() {
+                    return Err(anyhow!(
+                        "cannot focus button {}, there are only {}",
+                        focus,
+                        sheet.rects.len()
+                    ));
+                }
+                state.pressed.push(focus);
+            }
+            state
+                .hover
+                .extend(self.pointer.and_then(|(x, y)| sheet.in_button(x, y)));
+
+            let (width, height) = scaled(sheet.bounds, self.scale)?;
+            let mut image = Image::new(width, height);
+            draw(cfg, &sheet, &state, &mut image.canvas(), self.scale);
+
+            let bytes = match self.path.extension().and_then(|ext| ext.to_str()) {
+                Some("png") => image.png(),
+                Some("ppm") => image.ppm(),
+                _ => return Err(anyhow!("can only render to a .png or .ppm file")),
+            };
+            std::fs::File::create(&self.path)
+                .and_then(|mut file| file.write_all(&bytes))
+                .with_context(|| format!("failed to write {}", self.path.display()))
+        }
     }
 }
 
 mod pixbuf {
     use super::Data;
-    use anyhow::{Context, Result};
+    use anyhow::{anyhow, Context, Result};
+    use std::fs::File;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
     use wayland_client::protocol::{
         wl_buffer::{self, WlBuffer},
         wl_shm::{self, WlShm},
@@ -422,13 +2676,29 @@ mod pixbuf {
     #[derive(Debug)]
     pub struct ShmPixelBuffer {
         pub wl: Main<WlBuffer>,
+        /// held by the compositor until it sends Release
         pub locked: bool,
+    }
+
+    /// Equally sized buffers side by side in one shm pool, so there is always
+    /// one to draw in while the compositor holds another
+    #[derive(Debug)]
+    pub struct BufferPool {
+        pub buffers: Vec<ShmPixelBuffer>,
         pub width: usize,
         pub height: usize,
         addr: *mut u32,
     }
 
-    impl std::ops::Index<(usize, usize)> for ShmPixelBuffer {
+    /// Premultiplied ARGB pixels, row by row
+    #[derive(Debug)]
+    pub struct Canvas<'p> {
+        pub pixels: &'p mut [u32],
+        pub width: usize,
+        pub height: usize,
+    }
+
+    impl std::ops::Index<(usize, usize)> for Canvas<'_> {
         type Output = u32;
         fn index(&self, (x, y): (usize, usize)) -> &Self::Output {
             if x >= self.width || y >= self.height {
@@ -437,16 +2707,11 @@ mod pixbuf {
                     x, y, self.width, self.height
                 );
             }
-            unsafe {
-                self.addr
-                    .offset((x + y * self.width) as isize)
-                    .as_ref()
-                    .unwrap()
-            }
+            &self.pixels[x + y * self.width]
         }
     }
 
-    impl std::ops::IndexMut<(usize, usize)> for ShmPixelBuffer {
+    impl std::ops::IndexMut<(usize, usize)> for Canvas<'_> {
         fn index_mut(&mut self, (x, y): (usize, usize)) -> &mut Self::Output {
             if x >= self.width || y >= self.height {
                 panic!(
@@ -454,28 +2719,204 @@ mod pixbuf {
                     x, y, self.width, self.height
                 );
             }
+            &mut self.pixels[x + y * self.width]
+        }
+    }
+
+    impl Drop for BufferPool {
+        fn drop(&mut self) {
+            let len = self.buffers.len() * self.width * self.height * 4;
+            for buffer in self.buffers.iter() {
+                buffer.wl.destroy();
+            }
             unsafe {
-                self.addr
-                    .offset((x + y * self.width) as isize)
-                    .as_mut()
-                    .unwrap()
+                libc::munmap(self.addr as *mut libc::c_void, len);
+            }
+        }
+    }
+
+    impl BufferPool {
+        /// The first buffer the compositor is not holding
+        pub fn free(&self) -> Option<usize> {
+            self.buffers.iter().position(|b| !b.locked)
+        }
+
+        pub fn canvas(&mut self, index: usize) -> Canvas<'_> {
+            let len = self.width * self.height;
+            let pixels = unsafe { std::slice::from_raw_parts_mut(self.addr.add(index * len), len) };
+            Canvas {
+                pixels,
+                width: self.width,
+                height: self.height,
+            }
+        }
+
+        /// Unlock `buffer` if it is one of ours
+        pub fn release(&mut self, buffer: &WlBuffer) -> bool {
+            let released =
+                (self.buffers.iter_mut()).find(|b| b.wl.as_ref().equals(buffer.as_ref()));
+            released.map(|b| b.locked = false).is_some()
+        }
+    }
+
+    impl Canvas<'_> {
+        /// Composite premultiplied `src` over the pixel at `(x, y)`
+        pub fn blend(&mut self, (x, y): (usize, usize), src: u32) {
+            let dst = &mut self[(x, y)];
+            *dst = over(src, *dst);
+        }
+    }
+
+    /// A canvas in memory, for rendering without a compositor
+    #[derive(Debug)]
+    pub struct Image {
+        pixels: Vec<u32>,
+        width: usize,
+        height: usize,
+    }
+
+    impl Image {
+        pub fn new(width: usize, height: usize) -> Image {
+            Image {
+                pixels: vec![0; width * height],
+                width,
+                height,
+            }
+        }
+
+        pub fn canvas(&mut self) -> Canvas<'_> {
+            Canvas {
+                pixels: &mut self.pixels,
+                width: self.width,
+                height: self.height,
+            }
+        }
+
+        /// Binary PPM, which has no alpha, so as if composited over black
+        pub fn ppm(&self) -> Vec<u8> {
+            let mut out = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+            for pixel in &self.pixels {
+                out.extend_from_slice(&pixel.to_be_bytes()[1..]);
+            }
+            out
+        }
+
+        /// Straight alpha RGBA PNG, left uncompressed so it needs no deflate
+        pub fn png(&self) -> Vec<u8> {
+            let mut raw = Vec::with_capacity((self.width * 4 + 1) * self.height);
+            for row in self.pixels.chunks(std::cmp::max(self.width, 1)) {
+                raw.push(0); // no filter
+                for &pixel in row {
+                    let [a, r, g, b] = unpremultiply(pixel).to_be_bytes();
+                    raw.extend_from_slice(&[r, g, b, a]);
+                }
+            }
+
+            // zlib stream of stored deflate blocks
+            let mut zlib = vec![0x78, 0x01];
+            let mut blocks = raw.chunks(0xffff).peekable();
+            if blocks.peek().is_none() {
+                zlib.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+            }
+            while let Some(block) = blocks.next() {
+                let len = block.len() as u16;
+                zlib.push(blocks.peek().is_none() as u8);
+                zlib.extend_from_slice(&len.to_le_bytes());
+                zlib.extend_from_slice(&(!len).to_le_bytes());
+                zlib.extend_from_slice(block);
             }
+            zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+            let mut ihdr = vec![];
+            ihdr.extend_from_slice(&(self.width as u32).to_be_bytes());
+            ihdr.extend_from_slice(&(self.height as u32).to_be_bytes());
+            ihdr.extend_from_slice(&[8, 6, 0, 0, 0]); // 8 bit RGBA, not interlaced
+
+            let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+            for (kind, data) in [(b"IHDR", &ihdr), (b"IDAT", &zlib), (b"IEND", &vec![])] {
+                out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+                let start = out.len();
+                out.extend_from_slice(kind);
+                out.extend_from_slice(data);
+                let crc = crc32(&out[start..]);
+                out.extend_from_slice(&crc.to_be_bytes());
+            }
+            out
         }
     }
 
-    pub fn create_shmbuffer(
+    fn adler32(bytes: &[u8]) -> u32 {
+        let (a, b) = bytes.iter().fold((1u32, 0u32), |(a, b), &byte| {
+            let a = (a + byte as u32) % 65521;
+            (a, (b + a) % 65521)
+        });
+        (b << 16) | a
+    }
+
+    fn crc32(bytes: &[u8]) -> u32 {
+        !bytes.iter().fold(!0u32, |crc, &byte| {
+            (0..8).fold(crc ^ byte as u32, |crc, _| {
+                if crc & 1 == 1 {
+                    (crc >> 1) ^ 0xedb88320
+                } else {
+                    crc >> 1
+                }
+            })
+        })
+    }
+
+    /// `a * b / 255`, rounded to nearest
+    fn mul(a: u8, b: u8) -> u8 {
+        let t = a as u32 * b as u32 + 128;
+        ((t + (t >> 8)) >> 8) as u8
+    }
+
+    /// Convert straight ARGB, as colors are given on the command line, to the
+    /// premultiplied ARGB that wl_shm buffers hold
+    pub fn premultiply(argb: u32) -> u32 {
+        let [a, r, g, b] = argb.to_be_bytes();
+        u32::from_be_bytes([a, mul(r, a), mul(g, a), mul(b, a)])
+    }
+
+    pub fn unpremultiply(argb: u32) -> u32 {
+        let [a, r, g, b] = argb.to_be_bytes();
+        let div = |c: u8| match a {
+            0 => 0,
+            a => ((c as u32 * 255 + a as u32 / 2) / a as u32) as u8,
+        };
+        u32::from_be_bytes([a, div(r), div(g), div(b)])
+    }
+
+    /// Scale premultiplied `color` by a glyph's `coverage` of the pixel
+    pub fn coverage(color: u32, coverage: u8) -> u32 {
+        u32::from_be_bytes(color.to_be_bytes().map(|c| mul(c, coverage)))
+    }
+
+    /// Porter-Duff `src` over `dst`, both premultiplied ARGB
+    pub fn over(src: u32, dst: u32) -> u32 {
+        let (s, d) = (src.to_be_bytes(), dst.to_be_bytes());
+        let inv = 255 - s[0];
+        u32::from_be_bytes([0, 1, 2, 3].map(|i| s[i].saturating_add(mul(d[i], inv))))
+    }
+
+    pub fn create_buffer_pool(
         width: usize,
         height: usize,
+        count: usize,
         shm: &Main<WlShm>,
-    ) -> Result<ShmPixelBuffer> {
+    ) -> Result<BufferPool> {
         let fd = nix::unistd::mkstemp("/dev/shm/shmbuf_XXXXXX")
             .and_then(|(fd, path)| nix::unistd::unlink(path.as_path()).and(Ok(fd)))
             .context("Failed to create temp file fd for shm")?;
+        // create_pool sends a dup of the fd, so ours is closed when this drops,
+        // whether or not we got that far
+        let file = unsafe { File::from_raw_fd(fd) };
         let (format, pixel_size) = (wl_shm::Format::Argb8888, 4);
         let stride: i32 = width as i32 * pixel_size;
-        let size: usize = stride as usize * height;
+        let size: usize = stride as usize * height * count;
 
-        nix::unistd::ftruncate(fd, size as i64).context("Failed calling ftruncate")?;
+        file.set_len(size as u64)
+            .context("Failed calling ftruncate")?;
 
         let shmdata: *mut u32 = unsafe {
             let data = libc::mmap(
@@ -483,91 +2924,273 @@ mod pixbuf {
                 size,
                 libc::PROT_READ | libc::PROT_WRITE,
                 libc::MAP_SHARED,
-                fd,
+                file.as_raw_fd(),
                 0,
             );
             // checking for null is not in the manpage example, can you mmap 0x0?
             if data == libc::MAP_FAILED || data.is_null() {
-                libc::close(fd);
-                panic!("map failed");
+                let err = std::io::Error::last_os_error();
+                return Err(anyhow!("Failed to map shm: {}", err));
             }
             data as *mut u32
         };
 
-        let pool = shm.create_pool(fd, size as i32);
-        let buffer = pool.create_buffer(0, width as i32, height as i32, stride, format);
-        pool.destroy();
-
-        filter!(buffer, data,
-            wl_buffer::Event::Release => {
-                data.buffer.locked = false;
-            }
+        let pool = shm.create_pool(file.as_raw_fd(), size as i32);
+        // the filter! macro hides which buffer was released
+        let release = Filter::new(
+            |(buffer, ev): (Main<WlBuffer>, wl_buffer::Event), _filter, mut ddata| {
+                if let (wl_buffer::Event::Release, Some(data)) = (ev, ddata.get::<Data>()) {
+                    for surface in data.surfaces.iter_mut() {
+                        surface.buffers.release(&buffer);
+                    }
+                }
+            },
         );
+        let buffers = (0..count)
+            .map(|i| {
+                let offset = i as i32 * stride * height as i32;
+                let wl = pool.create_buffer(offset, width as i32, height as i32, stride, format);
+                wl.assign(release.clone());
+                ShmPixelBuffer { wl, locked: false }
+            })
+            .collect();
+        pool.destroy();
 
-        Ok(ShmPixelBuffer {
-            wl: buffer,
-            locked: false,
+        Ok(BufferPool {
+            buffers,
             addr: shmdata,
-            width: width,
-            height: height,
+            width,
+            height,
         })
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn premultiply_scales_color_by_alpha() {
+            assert_eq!(premultiply(0xffabcdef), 0xffabcdef);
+            assert_eq!(premultiply(0x00abcdef), 0x00000000);
+            assert_eq!(premultiply(0x80ff8000), 0x80804000);
+        }
+
+        #[test]
+        fn opaque_source_replaces_destination() {
+            assert_eq!(over(0xff123456, 0xffabcdef), 0xff123456);
+            assert_eq!(over(0xff123456, 0x00000000), 0xff123456);
+        }
+
+        #[test]
+        fn transparent_source_keeps_destination() {
+            assert_eq!(over(0x00000000, 0xffabcdef), 0xffabcdef);
+            assert_eq!(over(0x00000000, 0x40102030), 0x40102030);
+        }
+
+        #[test]
+        fn dark_text_shows_on_light_background() {
+            // the old max() blend left white untouched by black text
+            let black = premultiply(0xff000000);
+            assert_eq!(over(coverage(black, 255), 0xffffffff), 0xff000000);
+            assert_eq!(over(coverage(black, 128), 0xffffffff), 0xff7f7f7f);
+        }
+
+        #[test]
+        fn half_coverage_mixes_evenly() {
+            let white = premultiply(0xffffffff);
+            assert_eq!(coverage(white, 128), 0x80808080);
+            assert_eq!(over(coverage(white, 128), 0xff000000), 0xff808080);
+        }
+
+        #[test]
+        fn translucent_over_translucent() {
+            // 50% red over 50% blue: alpha 0.5 + 0.5 * 0.5
+            let red = premultiply(0x80ff0000);
+            let blue = premultiply(0x800000ff);
+            assert_eq!(over(red, blue), 0xc0800040);
+        }
+
+        #[test]
+        fn result_stays_premultiplied() {
+            for src in [0x80ff8000, 0x22222222, 0xddff9900, 0x01ffffff] {
+                for dst in [0xffffffff, 0x80404040, 0x00000000, 0xdd222222] {
+                    let [a, r, g, b] = over(premultiply(src), premultiply(dst)).to_be_bytes();
+                    assert!(r <= a && g <= a && b <= a, "{:08x} over {:08x}", src, dst);
+                }
+            }
+        }
+    }
 }
-use pixbuf::{create_shmbuffer, ShmPixelBuffer};
+use pixbuf::{create_buffer_pool, BufferPool};
 
 fn init_registry(display: &Display, event_queue: &mut EventQueue) -> Result<Registry> {
     let disp_proxy = display.attach(event_queue.token());
 
-    let gm = GlobalManager::new(&disp_proxy);
-    event_queue.dispatch(&mut (), |_, _, _| {})?;
-    let compositor: Main<WlCompositor> = gm
-        .instantiate_exact(4)
-        .context("Failed to get compositor handle")?;
-    let seat: Main<WlSeat> = gm
-        .instantiate_exact(5)
-        .context("Failed to get seat handle")?;
-    let wmbase: Main<XdgWmBase> = gm
-        .instantiate_exact(2)
-        .context("Failed to get wmbase handle")?;
-    let shm: Main<WlShm> = gm
-        .instantiate_exact(1)
-        .context("Failed to get shm handle")?;
-    let layer_shell: Main<LayerShell> = gm
-        .instantiate_range(2, 5)
-        .context("Failed to get layer shell handle")?;
-
-    Ok(Registry {
-        compositor,
-        seat,
-        wmbase,
-        shm,
-        layer_shell,
-    })
+    // seats and outputs come and go, so they are bound as they are announced
+    // rather than instantiated once
+    let gm = GlobalManager::new_with_cb(&disp_proxy, |ev, registry, mut ddata| match ev {
+        GlobalEvent::New {
+            id,
+            interface,
+            version,
+        } if interface == "wl_seat" => {
+            let seat = Seat::new(id, registry.bind::<WlSeat>(std::cmp::min(version, 5), id));
+            if let Some(data) = ddata.get::<Data>() {
+                data.registry.seats.push(seat);
+            } else if let Some((seats, _)) = ddata.get::<(Vec<Seat>, Vec<Output>)>() {
+                seats.push(seat);
+            }
+        }
+        GlobalEvent::New {
+            id,
+            interface,
+            version,
+        } if interface == "wl_output" => {
+            let output = Output::new(id, registry.bind::<WlOutput>(std::cmp::min(version, 3), id));
+            if let Some(data) = ddata.get::<Data>() {
+                if let Some(manager) = &data.registry.xdg_output {
+                    output.describe(manager);
+                }
+                data.registry.outputs.push(output);
+            } else if let Some((_, outputs)) = ddata.get::<(Vec<Seat>, Vec<Output>)>() {
+                outputs.push(output);
+            }
+        }
+        GlobalEvent::Removed { id, interface } if interface == "wl_seat" => {
+            if let Some(data) = ddata.get::<Data>() {
+                data.remove_seat(id);
+            }
+        }
+        GlobalEvent::Removed { id, interface } if interface == "wl_output" => {
+            if let Some(data) = ddata.get::<Data>() {
+                data.remove_output(id);
+            }
+        }
+        _ => {}
+    });
+    let mut devices: (Vec<Seat>, Vec<Output>) = (vec![], vec![]);
+    // the globals need not all arrive in one read
+    event_queue.sync_roundtrip(&mut devices, |_, _, _| {})?;
+    let (seats, outputs) = devices;
+    let mut missing = vec![];
+    let compositor = bind::<WlCompositor>(&gm, (1, 4), &mut missing);
+    let shm = bind::<WlShm>(&gm, (1, 1), &mut missing);
+    let layer_shell = gm.instantiate_range::<LayerShell>(1, 3).ok();
+    let wmbase = match layer_shell {
+        Some(_) => gm.instantiate_range::<XdgWmBase>(1, 3).ok(),
+        None => bind::<XdgWmBase>(&gm, (1, 3), &mut missing),
+    };
+    let fractional_scale = gm.instantiate_range(1, 1).ok();
+    let viewporter = gm.instantiate_range(1, 1).ok();
+    let xdg_output = gm.instantiate_range(1, 3).ok();
+    if layer_shell.is_none() && wmbase.is_none() {
+        missing.push(format!("or else {}", LayerShell::NAME));
+    }
+    match (compositor, shm) {
+        (Some(compositor), Some(shm)) if missing.is_empty() => Ok(Registry {
+            compositor,
+            seats,
+            outputs,
+            wmbase,
+            shm,
+            layer_shell,
+            fractional_scale,
+            viewporter,
+            xdg_output,
+        }),
+        _ => Err(anyhow!("missing required globals: {}", missing.join(", "))),
+    }
+}
+
+/// Bind global `I` at the newest version in `min..=max` it is offered at,
+/// or else note in `missing` why it can't be
+fn bind<I>(gm: &GlobalManager, (min, max): (u32, u32), missing: &mut Vec<String>) -> Option<Main<I>>
+where
+    I: Interface + AsRef<Proxy<I>> + From<Proxy<I>>,
+{
+    gm.instantiate_range(min, max)
+        .map_err(|err| match err {
+            GlobalError::Missing => missing.push(I::NAME.to_owned()),
+            GlobalError::VersionTooLow(version) => {
+                missing.push(format!("{} (version {} < {})", I::NAME, version, min))
+            }
+        })
+        .ok()
 }
 
-fn parse_config(mut args: std::env::Args, stdin: std::io::StdinLock) -> Result<Config> {
+fn parse_config(args: std::env::Args, mut stdin: std::io::StdinLock) -> Result<Config> {
     let mut border = 1usize;
-    let (mut bw, mut bh) = (300usize, 0usize);
+    let (mut sizing, mut bh) = (Sizing::Fixed(300), 0usize);
+    let mut padding = 10usize;
+    let mut overflow = Overflow::default();
     let (mut nf, mut nb, mut sf, mut sb) =
         (0xffddddddu32, 0xdd222222u32, 0xffddddddu32, 0xffff9900u32);
-    let mut font: Option<Font> = None;
-
-    args.next();
+    let (mut hf, mut hb) = (0xffffffffu32, 0xdd444444u32);
+    let mut fonts: Vec<String> = vec![];
+    let mut font_size = font::DEFAULT_SIZE;
+    let mut matcher = Match::default();
+    let mut layout = Layout::default();
+    let mut placement = Placement::default();
+    let (mut render_to, mut pointer, mut focus) = (None, None, None);
+    let (mut scale, mut size) = (None, None);
+    let mut delimiter = None;
+    let mut format = Format::default();
+    let mut json = false;
+
+    let mut args = args.skip(1).peekable();
     loop {
+        // the one flag without an argument
+        if args.next_if(|flag| flag == "--json").is_some() {
+            json = true;
+            continue;
+        }
         match (args.next(), args.next()) {
             (Some(flag), Some(arg)) => match flag.as_str() {
                 "-b" => border = arg.parse()?,
-                "-w" => bw = arg.parse()?,
+                "-w" => sizing = arg.parse()?,
                 "-h" => bh = arg.parse()?,
-                "-f" => {
-                    font = font.or(Font::load(&arg)
-                        .map_err(|err| eprintln!("failed to load font {}: {}", arg, err))
-                        .ok())
-                }
+                "-p" => padding = arg.parse()?,
+                "-t" => overflow = arg.parse()?,
+                "-f" => fonts.push(arg),
+                "-fs" => match arg.parse()? {
+                    size if f32::is_finite(size) && size > 0.0 => font_size = size,
+                    _ => Err(anyhow!("-fs must be a font size greater than 0"))?,
+                },
                 "-nf" => nf = arg.parse::<Argb>()?.0,
                 "-nb" => nb = arg.parse::<Argb>()?.0,
                 "-sf" => sf = arg.parse::<Argb>()?.0,
                 "-sb" => sb = arg.parse::<Argb>()?.0,
+                "-hf" => hf = arg.parse::<Argb>()?.0,
+                "-hb" => hb = arg.parse::<Argb>()?.0,
+                "-m" => matcher = arg.parse()?,
+                "-l" => layout = arg.parse()?,
+                "--anchor" => placement.anchor = arg.parse::<Anchor>()?.0,
+                "--margin" => placement.margin = arg.parse::<Margin>()?.0,
+                "--layer" => placement.layer = arg.parse::<ShellLayer>()?.0,
+                "--exclusive-zone" => placement.exclusive_zone = arg.parse()?,
+                "--namespace" => placement.namespace = arg,
+                "--output" => placement.output = arg.parse()?,
+                // a tab is awkward to pass as an argument
+                "--delimiter" if arg == "\\t" => delimiter = Some(String::from("\t")),
+                "--delimiter" if arg.is_empty() => Err(anyhow!("--delimiter must not be empty"))?,
+                "--delimiter" => delimiter = Some(arg),
+                "--format" => format = arg.parse()?,
+                "--render-to" => render_to = Some(std::path::PathBuf::from(arg)),
+                "--pointer" => {
+                    let (x, y) = (arg.split_once(','))
+                        .ok_or_else(|| anyhow!("--pointer must be given as X,Y"))?;
+                    pointer = Some((x.parse()?, y.parse()?));
+                }
+                "--size" => {
+                    let (width, height) = (arg.split_once('x'))
+                        .ok_or_else(|| anyhow!("--size must be given as WIDTHxHEIGHT"))?;
+                    size = Some((width.parse()?, height.parse()?));
+                }
+                "--focus" => focus = Some(arg.parse()?),
+                "--scale" => match arg.parse()? {
+                    factor if f64::is_finite(factor) && factor > 0.0 => scale = Some(factor),
+                    _ => Err(anyhow!("--scale must be a factor greater than 0"))?,
+                },
                 _ => {
                     Err(anyhow!("Unrecognized argument {}", flag))?;
                 }
@@ -577,33 +3200,83 @@ fn parse_config(mut args: std::env::Args, stdin: std::io::StdinLock) -> Result<C
         }
     }
 
-    let options = stdin.lines().fold(Ok(vec![]), |acc, x| match (acc, x) {
-        (Ok(acc), Ok(s)) if s.len() == 0 => Ok(acc),
-        (Ok(mut acc), Ok(s)) => Ok({
-            acc.push(s);
-            acc
+    let font = Font::open_all(&fonts, font_size)?;
+    let snapshot = match render_to {
+        Some(path) => Some(Snapshot {
+            path,
+            pointer,
+            focus,
+            scale: scale.unwrap_or(1.0),
+            size: size.unwrap_or((0, 0)),
         }),
-        (Err(err), _) => Err(err),
-        (Ok(_), Err(err)) => Err(err),
-    })?;
+        None if pointer.is_some() || focus.is_some() || scale.is_some() || size.is_some() => Err(
+            anyhow!("--pointer, --focus, --scale and --size only apply with --render-to"),
+        )?,
+        None => None,
+    };
+
+    let options = if json {
+        if delimiter.is_some() {
+            Err(anyhow!("--delimiter does not apply to --json"))?;
+        }
+        let mut input = String::new();
+        stdin.read_to_string(&mut input)?;
+        json::items(&input)?
+    } else {
+        stdin.lines().try_fold(vec![], |mut acc, x| {
+            x.map(|s| {
+                if !s.is_empty() {
+                    acc.push(Item::parse(&s, delimiter.as_deref()));
+                }
+                acc
+            })
+        })?
+    };
 
-    Ok(Config {
+    let mut cfg = Config {
         options,
-        font: font.unwrap_or_else(Font::default),
-        button_dim: (bw, if bh != 0 { bh } else { bw }),
+        matcher,
+        query: String::new(),
+        visible: vec![],
+        font,
+        button_sizes: vec![],
         border,
+        padding,
+        overflow,
+        layout,
+        bounds: (0, 0),
+        placement,
+        format,
+        snapshot,
         nf,
         nb,
         sf,
         sb,
-        should_close: false,
-    })
+        hf,
+        hb,
+        exit: None,
+    };
+    cfg.size_buttons(sizing, bh);
+    cfg.refilter();
+    Ok(cfg)
+}
+
+fn main() {
+    let exit = run().unwrap_or_else(|err| {
+        eprintln!("Error: {:?}", err);
+        Exit::Error
+    });
+    std::process::exit(exit as i32);
 }
 
-fn main() -> Result<()> {
-    let cfg = parse_config(std::env::args(), std::io::stdin().lock())?;
-    if cfg.options.len() == 0 {
-        return Ok(());
+fn run() -> Result<Exit> {
+    let mut cfg = parse_config(std::env::args(), std::io::stdin().lock())?;
+    if cfg.options.is_empty() {
+        return Ok(Exit::NoInput);
+    }
+    if let Some(snapshot) = cfg.snapshot.take() {
+        snapshot.write(&cfg)?;
+        return Ok(Exit::Selected);
     }
 
     let display = Display::connect_to_env().context("failed to connect to display")?;
@@ -611,38 +3284,31 @@ fn main() -> Result<()> {
     let registry = init_registry(&display, &mut event_queue)
         .context("failed to get necessary handles for registry")?;
     let mut data = Data::new(cfg, registry);
+    // outputs are named in reply to Data::new asking
+    event_queue
+        .sync_roundtrip(&mut data, |_, _, _| {})
+        .context("failed to get output names")?;
+    data.show()?;
 
-    while !data.cfg.should_close {
+    loop {
+        if let Some(exit) = data.cfg.exit {
+            return Ok(exit);
+        }
         event_queue
             .dispatch(&mut data, |_, _, _| {})
             .context("An error occurred during event dispatch")?;
 
-        if data.ptr.frame
-            && (data.ptr.pos_prev.is_some() ^ data.ptr.pos.is_some()
-                || data.ptr.btn != data.ptr.btn_prev)
+        data.process_input();
+
+        if let Some((i, opt)) = data
+            .selected
+            .and_then(|i| Some((i, data.cfg.options.get(i)?)))
         {
-            data.ptr.btn_prev = data.ptr.btn;
-            data.ptr.pos_prev = data.ptr.pos;
-            data.render();
-
-            if let Some(opt) = (data.ptr.btn)
-                .filter(|btn| btn == &wl_pointer::ButtonState::Released)
-                .and(data.ptr.pos)
-                .and_then(|(x, y)| data.cfg.in_button(x.ceil() as usize, y.ceil() as usize))
-                .and_then(|i| data.cfg.options.get(i))
-            {
-                println!("{}", opt);
-                data.cfg.should_close = true;
-            }
+            let button = data.cfg.visible.iter().position(|&v| v == i).unwrap_or(0);
+            println!("{}", data.cfg.format.print(opt, i, button));
+            data.cfg.exit = Some(Exit::Selected);
         }
 
-        if data.surface.configured && !data.surface.committed {
-            data.surface.wl.attach(Some(&data.buffer.wl), 0, 0);
-            data.buffer.locked = true;
-            data.surface.wl.commit();
-            data.surface.committed = true;
-        }
+        data.frame();
     }
-
-    Ok(())
 }
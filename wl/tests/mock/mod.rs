@@ -0,0 +1,763 @@
+//! A fake compositor for end-to-end tests. It speaks just enough of the wayland
+//! wire protocol, over a socketpair handed to wl as `WAYLAND_SOCKET`, to show the
+//! menu, read back what it draws and feed it input.
+
+use nix::errno::Errno;
+use nix::sys::socket::{
+    recvmsg, sendmsg, socketpair, AddressFamily, ControlMessage, ControlMessageOwned, MsgFlags,
+    SockFlag, SockType,
+};
+use nix::sys::uio::IoVec;
+use std::collections::{HashMap, VecDeque};
+use std::ffi::CStr;
+use std::io::Write;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Output, Stdio};
+use std::time::Duration;
+use xkbcommon_dl::{
+    xkb_context_flags, xkb_keymap_compile_flags, xkb_keymap_format, xkb_rule_names,
+};
+
+/// Everything the menu can use, at the versions offered
+pub const GLOBALS: [(&str, u32); 5] = [
+    ("wl_compositor", 4),
+    ("wl_shm", 1),
+    ("wl_seat", 5),
+    ("xdg_wm_base", 3),
+    ("zwlr_layer_shell_v1", 3),
+];
+
+/// evdev codes, as wl_pointer and wl_keyboard send them
+pub const BTN_LEFT: u32 = 0x110;
+pub const KEY_ESC: u32 = 1;
+pub const KEY_TAB: u32 = 15;
+pub const KEY_E: u32 = 18;
+pub const KEY_R: u32 = 19;
+pub const KEY_ENTER: u32 = 28;
+pub const KEY_B: u32 = 48;
+
+/// How long the client may keep us waiting before the test fails
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arg {
+    Int(i32),
+    Uint(u32),
+    Fixed(f64),
+    Str(String),
+    /// 0 for null
+    Object(u32),
+    NewId(u32),
+    Array(Vec<u8>),
+    Fd(RawFd),
+}
+
+#[derive(Debug)]
+pub struct Request {
+    pub interface: String,
+    pub name: &'static str,
+    pub args: Vec<Arg>,
+}
+
+/// The contents of a committed buffer, premultiplied ARGB
+#[derive(Debug)]
+pub struct Frame {
+    /// the wl_surface it was committed to
+    pub surface: u32,
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+}
+
+impl Frame {
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let [_, r, g, b] = self.pixels[x + y * self.width].to_be_bytes();
+        [r, g, b]
+    }
+}
+
+#[derive(Debug)]
+struct Buffer {
+    fd: RawFd,
+    offset: i32,
+    width: i32,
+    height: i32,
+    stride: i32,
+}
+
+#[derive(Debug)]
+pub struct Mock {
+    child: Option<Child>,
+    stream: UnixStream,
+    globals: Vec<(&'static str, u32)>,
+    incoming: Vec<u8>,
+    fds: VecDeque<RawFd>,
+    /// interface and version of every object the client created
+    objects: HashMap<u32, (String, u32)>,
+    pools: HashMap<u32, RawFd>,
+    buffers: HashMap<u32, Buffer>,
+    /// the buffer each surface will show on its next commit
+    attached: HashMap<u32, u32>,
+    /// frame callbacks waiting on each surface's next commit
+    callbacks: HashMap<u32, Vec<u32>>,
+    serial: u32,
+    /// every request the client sent, in order
+    pub requests: Vec<Request>,
+    /// every buffer the client committed, in order
+    pub frames: Vec<Frame>,
+}
+
+impl Drop for Mock {
+    fn drop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        for &fd in self.pools.values().chain(self.fds.iter()) {
+            let _ = nix::unistd::close(fd);
+        }
+    }
+}
+
+impl Mock {
+    /// Run wl with `args` and `input` on stdin, against a compositor with every global
+    pub fn spawn(args: &[&str], input: &str) -> Mock {
+        Mock::spawn_with(&GLOBALS, args, input)
+    }
+
+    pub fn spawn_with(globals: &[(&'static str, u32)], args: &[&str], input: &str) -> Mock {
+        let (server, client) = socketpair(
+            AddressFamily::Unix,
+            SockType::Stream,
+            None,
+            SockFlag::SOCK_CLOEXEC,
+        )
+        .expect("failed to create a socketpair");
+        let mut command = Command::new(env!("CARGO_BIN_EXE_wl"));
+        command
+            .args(args)
+            .env("WAYLAND_SOCKET", client.to_string())
+            .env_remove("WAYLAND_DISPLAY")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        // only the client's end is inherited
+        unsafe {
+            command.pre_exec(move || {
+                let flags = nix::fcntl::FcntlArg::F_SETFD(nix::fcntl::FdFlag::empty());
+                nix::fcntl::fcntl(client, flags)
+                    .map(drop)
+                    .map_err(|_| std::io::Error::last_os_error())
+            });
+        }
+        let mut child = command.spawn().expect("failed to run wl");
+        let _ = nix::unistd::close(client);
+        // wl may well have exited already, which the test will see
+        let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+
+        let stream = unsafe { UnixStream::from_raw_fd(server) };
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+        let mut objects = HashMap::new();
+        objects.insert(1, (String::from("wl_display"), 1));
+        Mock {
+            child: Some(child),
+            stream,
+            globals: globals.to_vec(),
+            incoming: vec![],
+            fds: VecDeque::new(),
+            objects,
+            pools: HashMap::new(),
+            buffers: HashMap::new(),
+            attached: HashMap::new(),
+            callbacks: HashMap::new(),
+            serial: 0,
+            requests: vec![],
+            frames: vec![],
+        }
+    }
+
+    /// Handle requests until `done`, returning false if the client hung up first
+    pub fn dispatch_until(&mut self, done: impl Fn(&Mock) -> bool) -> bool {
+        while !done(self) {
+            if !self.read() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// The arguments of every `interface.request` the client sent
+    pub fn called(&self, request: &str) -> Vec<&[Arg]> {
+        (self.requests.iter())
+            .filter(|r| format!("{}.{}", r.interface, r.name) == request)
+            .map(|r| &r.args[..])
+            .collect()
+    }
+
+    /// The first object of `interface` the client created
+    pub fn object(&self, interface: &str) -> Option<u32> {
+        self.objects(interface).first().copied()
+    }
+
+    /// Every object of `interface` the client created, oldest first
+    pub fn objects(&self, interface: &str) -> Vec<u32> {
+        let mut ids: Vec<_> = (self.objects.iter())
+            .filter(|(_, (i, _))| i == interface)
+            .map(|(&id, _)| id)
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    /// Announce another global to the client, returning its name
+    pub fn add_global(&mut self, global: &'static str, version: u32) -> u32 {
+        self.globals.push((global, version));
+        let name = self.globals.len() as u32;
+        let registry = self
+            .object("wl_registry")
+            .expect("the client has no registry");
+        let args = [Arg::Uint(name), Arg::Str(global.into()), Arg::Uint(version)];
+        self.send(registry, 0, &args);
+        name
+    }
+
+    /// Take the global called `name` away again
+    pub fn remove_global(&mut self, name: u32) {
+        let registry = self
+            .object("wl_registry")
+            .expect("the client has no registry");
+        self.send(registry, 1, &[Arg::Uint(name)]);
+    }
+
+    /// Tell the client which input devices `seat` has now, as wl_seat capability bits
+    pub fn set_capabilities(&mut self, seat: u32, capabilities: u32) {
+        self.send(seat, 0, &[Arg::Uint(capabilities)]);
+    }
+
+    /// Configure the menu's surface once it has made its initial commit,
+    /// 0 leaving that dimension to the client
+    pub fn configure(&mut self, width: u32, height: u32) {
+        let committed = |m: &Mock| !m.called("wl_surface.commit").is_empty();
+        self.expect("committing", committed);
+        if self.object("zwlr_layer_surface_v1").is_some() {
+            return self.configure_each(&[(width, height)]);
+        }
+        let serial = self.serial();
+        let toplevel = self
+            .object("xdg_toplevel")
+            .expect("the surface has no role");
+        let xdg = self.object("xdg_surface").unwrap();
+        let size = [Arg::Int(width as i32), Arg::Int(height as i32)];
+        self.send(toplevel, 0, &[&size[..], &[Arg::Array(vec![])]].concat());
+        self.send(xdg, 0, &[Arg::Uint(serial)]);
+    }
+
+    /// Configure the menu's first layer surfaces, one size each, once they have
+    /// all made their initial commit
+    pub fn configure_each(&mut self, sizes: &[(u32, u32)]) {
+        let committed = |m: &Mock| m.called("wl_surface.commit").len() >= sizes.len();
+        self.expect("committing", committed);
+        let layers = self.objects("zwlr_layer_surface_v1");
+        assert!(
+            layers.len() >= sizes.len(),
+            "only {} layer surfaces",
+            layers.len()
+        );
+        for (&layer, &(width, height)) in layers.iter().zip(sizes) {
+            let serial = self.serial();
+            let args = [Arg::Uint(serial), Arg::Uint(width), Arg::Uint(height)];
+            self.send(layer, 0, &args);
+        }
+    }
+
+    /// Take the menu's surface away, or ask its window to close
+    pub fn close(&mut self) {
+        let role = (self.object("zwlr_layer_surface_v1"))
+            .or_else(|| self.object("xdg_toplevel"))
+            .expect("the surface has no role");
+        self.send(role, 1, &[]);
+    }
+
+    /// Wait for the client to commit a new buffer
+    pub fn next_frame(&mut self) -> &Frame {
+        let n = self.frames.len();
+        self.expect("drawing", |m| m.frames.len() > n);
+        self.frames.last().unwrap()
+    }
+
+    /// Move the pointer onto the menu at `(x, y)`, in logical pixels
+    pub fn pointer_enter(&mut self, x: f64, y: f64) {
+        let surface = self.object("wl_surface").unwrap();
+        self.pointer_enter_on(surface, x, y);
+    }
+
+    /// Move the pointer onto one of the menu's surfaces
+    pub fn pointer_enter_on(&mut self, surface: u32, x: f64, y: f64) {
+        let pointer = self.device("wl_pointer");
+        let serial = self.serial();
+        let args = [
+            Arg::Uint(serial),
+            Arg::Object(surface),
+            Arg::Fixed(x),
+            Arg::Fixed(y),
+        ];
+        self.send(pointer, 0, &args);
+        self.send(pointer, 5, &[]);
+    }
+
+    pub fn pointer_motion(&mut self, x: f64, y: f64) {
+        let pointer = self.device("wl_pointer");
+        self.send(pointer, 2, &[Arg::Uint(0), Arg::Fixed(x), Arg::Fixed(y)]);
+        self.send(pointer, 5, &[]);
+    }
+
+    /// Press and release the left button wherever the pointer is
+    pub fn click(&mut self) {
+        let pointer = self.device("wl_pointer");
+        for state in [1, 0] {
+            let serial = self.serial();
+            let args = [
+                Arg::Uint(serial),
+                Arg::Uint(0),
+                Arg::Uint(BTN_LEFT),
+                Arg::Uint(state),
+            ];
+            self.send(pointer, 3, &args);
+            self.send(pointer, 5, &[]);
+        }
+    }
+
+    /// Press and release evdev `key`
+    pub fn key(&mut self, key: u32) {
+        assert!(has_xkb(), "keyboard tests need libxkbcommon");
+        let keyboard = self.device("wl_keyboard");
+        for state in [1, 0] {
+            let serial = self.serial();
+            let args = [
+                Arg::Uint(serial),
+                Arg::Uint(0),
+                Arg::Uint(key),
+                Arg::Uint(state),
+            ];
+            self.send(keyboard, 3, &args);
+        }
+    }
+
+    /// Put touch point `id` down on the menu at `(x, y)`, in logical pixels
+    pub fn touch_down(&mut self, id: i32, x: f64, y: f64) {
+        let touch = self.device("wl_touch");
+        let surface = self.object("wl_surface").unwrap();
+        let serial = self.serial();
+        let args = [
+            Arg::Uint(serial),
+            Arg::Uint(0),
+            Arg::Object(surface),
+            Arg::Int(id),
+            Arg::Fixed(x),
+            Arg::Fixed(y),
+        ];
+        self.send(touch, 0, &args);
+        self.send(touch, 3, &[]);
+    }
+
+    pub fn touch_motion(&mut self, id: i32, x: f64, y: f64) {
+        let touch = self.device("wl_touch");
+        let args = [Arg::Uint(0), Arg::Int(id), Arg::Fixed(x), Arg::Fixed(y)];
+        self.send(touch, 2, &args);
+        self.send(touch, 3, &[]);
+    }
+
+    pub fn touch_up(&mut self, id: i32) {
+        let touch = self.device("wl_touch");
+        let serial = self.serial();
+        self.send(touch, 1, &[Arg::Uint(serial), Arg::Uint(0), Arg::Int(id)]);
+        self.send(touch, 3, &[]);
+    }
+
+    /// Keep answering the client until it exits, then collect its output
+    pub fn finish(mut self) -> Output {
+        while self.read() {}
+        let child = self.child.take().unwrap();
+        child.wait_with_output().unwrap()
+    }
+
+    fn device(&mut self, interface: &str) -> u32 {
+        let created = |m: &Mock| m.object(interface).is_some();
+        self.expect(&format!("asking for a {}", interface), created);
+        self.object(interface).unwrap()
+    }
+
+    /// Handle requests until `done`, failing with the client's errors if it exits first
+    fn expect(&mut self, what: &str, done: impl Fn(&Mock) -> bool) {
+        if !self.dispatch_until(done) {
+            let output = self.child.take().unwrap().wait_with_output().unwrap();
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            panic!("the client exited before {}: {}", what, stderr);
+        }
+    }
+
+    fn serial(&mut self) -> u32 {
+        self.serial += 1;
+        self.serial
+    }
+
+    /// Read and handle whatever the client sent, false once it hangs up
+    fn read(&mut self) -> bool {
+        let mut buf = [0u8; 4096];
+        let mut cmsgs = nix::cmsg_space!([RawFd; 28]);
+        let (len, fds) = {
+            let iov = [IoVec::from_mut_slice(&mut buf)];
+            let msg = match recvmsg(
+                self.stream.as_raw_fd(),
+                &iov,
+                Some(&mut cmsgs),
+                MsgFlags::MSG_CMSG_CLOEXEC,
+            ) {
+                Ok(msg) => msg,
+                // it exited before reading everything we sent, so there is no EOF
+                Err(nix::Error::Sys(Errno::ECONNRESET)) => return false,
+                Err(nix::Error::Sys(Errno::EAGAIN)) => {
+                    panic!("the client sent nothing for {:?}", TIMEOUT)
+                }
+                Err(err) => panic!("failed to read from the client: {}", err),
+            };
+            let fds: Vec<RawFd> = (msg.cmsgs())
+                .flat_map(|cmsg| match cmsg {
+                    ControlMessageOwned::ScmRights(fds) => fds,
+                    _ => vec![],
+                })
+                .collect();
+            (msg.bytes, fds)
+        };
+        self.fds.extend(fds);
+        self.incoming.extend_from_slice(&buf[..len]);
+
+        while self.incoming.len() >= 8 {
+            let word = |i: usize| u32::from_ne_bytes([0, 1, 2, 3].map(|b| self.incoming[i + b]));
+            let (object, header) = (word(0), word(4));
+            let size = (header >> 16) as usize;
+            if self.incoming.len() < size {
+                break;
+            }
+            let message: Vec<u8> = self.incoming.drain(..size).collect();
+            self.handle(object, header as u16, &message[8..]);
+        }
+        len > 0
+    }
+
+    fn handle(&mut self, object: u32, opcode: u16, body: &[u8]) {
+        let (interface, version) = (self.objects.get(&object).cloned())
+            .unwrap_or_else(|| panic!("request on unknown object {}", object));
+        let (name, signature, creates) = request(&interface, opcode)
+            .unwrap_or_else(|| panic!("the mock does not know {} request {}", interface, opcode));
+        let args = self.decode(signature, body);
+
+        for arg in args.iter() {
+            if let Arg::NewId(id) = arg {
+                let created = match (creates, &args[..]) {
+                    (Some(created), _) => (created.to_owned(), version),
+                    (None, [_, Arg::Str(interface), Arg::Uint(version), _]) => {
+                        (interface.clone(), *version)
+                    }
+                    _ => unreachable!("{}.{} creates nothing", interface, name),
+                };
+                self.objects.insert(*id, created);
+            }
+        }
+
+        match (interface.as_str(), name, &args[..]) {
+            ("wl_display", "sync", &[Arg::NewId(callback)]) => {
+                let serial = self.serial();
+                self.send(callback, 0, &[Arg::Uint(serial)]);
+                self.delete(callback);
+            }
+            ("wl_display", "get_registry", &[Arg::NewId(registry)]) => {
+                for (i, (global, version)) in self.globals.clone().into_iter().enumerate() {
+                    let args = [
+                        Arg::Uint(i as u32 + 1),
+                        Arg::Str(global.into()),
+                        Arg::Uint(version),
+                    ];
+                    self.send(registry, 0, &args);
+                }
+            }
+            ("wl_registry", "bind", [_, Arg::Str(global), Arg::Uint(version), Arg::NewId(id)]) => {
+                match global.as_str() {
+                    "wl_seat" => {
+                        // a pointer, a keyboard and a touchscreen
+                        self.send(*id, 0, &[Arg::Uint(7)]);
+                        if *version >= 2 {
+                            self.send(*id, 1, &[Arg::Str("seat0".into())]);
+                        }
+                    }
+                    "wl_shm" => {
+                        self.send(*id, 0, &[Arg::Uint(0)]);
+                        self.send(*id, 0, &[Arg::Uint(1)]);
+                    }
+                    _ => {}
+                }
+            }
+            ("wl_shm", "create_pool", &[Arg::NewId(pool), Arg::Fd(fd), _]) => {
+                self.pools.insert(pool, fd);
+            }
+            ("wl_shm_pool", "create_buffer", &[Arg::NewId(buffer), ref args @ .., _]) => {
+                let [offset, width, height, stride] = [0, 1, 2, 3].map(|i| match args[i] {
+                    Arg::Int(int) => int,
+                    _ => unreachable!("create_buffer takes ints"),
+                });
+                let fd = self.pools[&object];
+                let buffer_data = Buffer {
+                    fd,
+                    offset,
+                    width,
+                    height,
+                    stride,
+                };
+                self.buffers.insert(buffer, buffer_data);
+            }
+            ("wl_surface", "attach", &[Arg::Object(buffer), _, _]) => {
+                self.attached.insert(object, buffer);
+            }
+            ("wl_surface", "frame", &[Arg::NewId(callback)]) => {
+                self.callbacks.entry(object).or_default().push(callback);
+            }
+            ("wl_surface", "commit", _) => self.commit(object),
+            // without libxkbcommon, wl could not read a keymap either, and only
+            // tests that press keys need one
+            ("wl_seat", "get_keyboard", &[Arg::NewId(keyboard)]) if has_xkb() => {
+                self.send_keymap(keyboard)
+            }
+            (_, "destroy", _) | (_, "release", _) => self.delete(object),
+            _ => {}
+        }
+
+        self.requests.push(Request {
+            interface,
+            name,
+            args,
+        });
+    }
+
+    fn commit(&mut self, surface: u32) {
+        if let Some(id) = self.attached.remove(&surface).filter(|&id| id != 0) {
+            let buffer = &self.buffers[&id];
+            let (width, height) = (buffer.width as usize, buffer.height as usize);
+            let stride = buffer.stride as usize;
+            let mut bytes = vec![0; stride * height];
+            nix::sys::uio::pread(buffer.fd, &mut bytes, buffer.offset as i64)
+                .expect("failed to read a committed buffer");
+            let pixels = (bytes.chunks(stride))
+                .flat_map(|row| row[..width * 4].chunks(4))
+                .map(|p| u32::from_ne_bytes([p[0], p[1], p[2], p[3]]))
+                .collect();
+            self.frames.push(Frame {
+                surface,
+                width,
+                height,
+                pixels,
+            });
+            // it is copied, so the client can have it back straight away
+            self.send(id, 0, &[]);
+        }
+        // and draw its next frame as soon as it likes
+        for callback in self.callbacks.remove(&surface).unwrap_or_default() {
+            self.send(callback, 0, &[Arg::Uint(0)]);
+            self.delete(callback);
+        }
+    }
+
+    fn send_keymap(&mut self, keyboard: u32) {
+        let mut keymap = keymap().into_bytes();
+        keymap.push(0);
+        let (fd, path) = nix::unistd::mkstemp("/dev/shm/wl-keymap-XXXXXX")
+            .expect("failed to create a keymap file");
+        let _ = nix::unistd::unlink(path.as_path());
+        nix::unistd::write(fd, &keymap).expect("failed to write the keymap");
+        let args = [Arg::Uint(1), Arg::Fd(fd), Arg::Uint(keymap.len() as u32)];
+        self.send(keyboard, 0, &args);
+        let _ = nix::unistd::close(fd);
+    }
+
+    /// Tell the client it may reuse `id`
+    fn delete(&mut self, id: u32) {
+        self.send(1, 1, &[Arg::Uint(id)]);
+    }
+
+    fn send(&mut self, object: u32, opcode: u16, args: &[Arg]) {
+        let (mut body, mut fds) = (vec![], vec![]);
+        for arg in args {
+            match arg {
+                Arg::Int(i) => body.extend(&i.to_ne_bytes()),
+                Arg::Uint(u) | Arg::Object(u) | Arg::NewId(u) => body.extend(&u.to_ne_bytes()),
+                Arg::Fixed(f) => body.extend(&((f * 256.0) as i32).to_ne_bytes()),
+                Arg::Str(s) => {
+                    let mut bytes = s.clone().into_bytes();
+                    bytes.push(0);
+                    put_array(&mut body, &bytes);
+                }
+                Arg::Array(a) => put_array(&mut body, a),
+                Arg::Fd(fd) => fds.push(*fd),
+            }
+        }
+        let header = ((8 + body.len() as u32) << 16) | opcode as u32;
+        let message = [&object.to_ne_bytes()[..], &header.to_ne_bytes(), &body].concat();
+        let iov = [IoVec::from_slice(&message)];
+        let cmsgs = [ControlMessage::ScmRights(&fds)];
+        let cmsgs = if fds.is_empty() { &[][..] } else { &cmsgs[..] };
+        match sendmsg(
+            self.stream.as_raw_fd(),
+            &iov,
+            cmsgs,
+            MsgFlags::empty(),
+            None,
+        ) {
+            // the client hung up, which the next read sees; Rust ignores SIGPIPE
+            Ok(_) | Err(nix::Error::Sys(Errno::EPIPE | Errno::ECONNRESET)) => {}
+            Err(err) => panic!("failed to write to the client: {}", err),
+        }
+    }
+
+    fn decode(&mut self, signature: &str, body: &[u8]) -> Vec<Arg> {
+        let mut at = 0;
+        let word = |at: &mut usize| {
+            let w = u32::from_ne_bytes([0, 1, 2, 3].map(|b| body[*at + b]));
+            *at += 4;
+            w
+        };
+        let mut args = vec![];
+        for kind in signature.chars().filter(|&c| c != '?') {
+            let arg = match kind {
+                'i' => Arg::Int(word(&mut at) as i32),
+                'u' => Arg::Uint(word(&mut at)),
+                'f' => Arg::Fixed(word(&mut at) as i32 as f64 / 256.0),
+                'o' => Arg::Object(word(&mut at)),
+                'n' => Arg::NewId(word(&mut at)),
+                's' | 'a' => {
+                    let len = word(&mut at) as usize;
+                    let start = at;
+                    // padded to 32 bits
+                    at += (len + 3) & !3;
+                    let bytes = body[start..start + len].to_vec();
+                    match kind {
+                        's' => {
+                            let text = bytes.split(|&b| b == 0).next().unwrap_or_default();
+                            Arg::Str(String::from_utf8_lossy(text).into_owned())
+                        }
+                        _ => Arg::Array(bytes),
+                    }
+                }
+                'h' => Arg::Fd(self.fds.pop_front().expect("an fd went missing")),
+                _ => unreachable!("unknown argument type {}", kind),
+            };
+            args.push(arg);
+        }
+        args
+    }
+}
+
+/// Length-prefixed and padded to 32 bits
+fn put_array(body: &mut Vec<u8>, bytes: &[u8]) {
+    body.extend(&(bytes.len() as u32).to_ne_bytes());
+    body.extend(bytes);
+    body.resize((body.len() + 3) & !3, 0);
+}
+
+/// The name and signature of each request the menu might send, and the
+/// interface of the object it creates if that isn't given as an argument
+fn request(
+    interface: &str,
+    opcode: u16,
+) -> Option<(&'static str, &'static str, Option<&'static str>)> {
+    Some(match (interface, opcode) {
+        ("wl_display", 0) => ("sync", "n", Some("wl_callback")),
+        ("wl_display", 1) => ("get_registry", "n", Some("wl_registry")),
+        ("wl_registry", 0) => ("bind", "usun", None),
+        ("wl_compositor", 0) => ("create_surface", "n", Some("wl_surface")),
+        ("wl_compositor", 1) => ("create_region", "n", Some("wl_region")),
+        ("wl_shm", 0) => ("create_pool", "nhi", Some("wl_shm_pool")),
+        ("wl_shm_pool", 0) => ("create_buffer", "niiiiu", Some("wl_buffer")),
+        ("wl_shm_pool", 1) => ("destroy", "", None),
+        ("wl_shm_pool", 2) => ("resize", "i", None),
+        ("wl_buffer", 0) => ("destroy", "", None),
+        ("wl_surface", 0) => ("destroy", "", None),
+        ("wl_surface", 1) => ("attach", "?oii", None),
+        ("wl_surface", 2) => ("damage", "iiii", None),
+        ("wl_surface", 3) => ("frame", "n", Some("wl_callback")),
+        ("wl_surface", 4) => ("set_opaque_region", "?o", None),
+        ("wl_surface", 5) => ("set_input_region", "?o", None),
+        ("wl_surface", 6) => ("commit", "", None),
+        ("wl_surface", 7) => ("set_buffer_transform", "i", None),
+        ("wl_surface", 8) => ("set_buffer_scale", "i", None),
+        ("wl_surface", 9) => ("damage_buffer", "iiii", None),
+        ("wl_seat", 0) => ("get_pointer", "n", Some("wl_pointer")),
+        ("wl_seat", 1) => ("get_keyboard", "n", Some("wl_keyboard")),
+        ("wl_seat", 2) => ("get_touch", "n", Some("wl_touch")),
+        ("wl_seat", 3) => ("release", "", None),
+        ("wl_pointer", 0) => ("set_cursor", "u?oii", None),
+        ("wl_pointer", 1) => ("release", "", None),
+        ("wl_keyboard", 0) => ("release", "", None),
+        ("wl_touch", 0) => ("release", "", None),
+        ("wl_output", 0) => ("release", "", None),
+        ("xdg_wm_base", 0) => ("destroy", "", None),
+        ("xdg_wm_base", 1) => ("create_positioner", "n", Some("xdg_positioner")),
+        ("xdg_wm_base", 2) => ("get_xdg_surface", "no", Some("xdg_surface")),
+        ("xdg_wm_base", 3) => ("pong", "u", None),
+        ("xdg_surface", 0) => ("destroy", "", None),
+        ("xdg_surface", 1) => ("get_toplevel", "n", Some("xdg_toplevel")),
+        ("xdg_surface", 3) => ("set_window_geometry", "iiii", None),
+        ("xdg_surface", 4) => ("ack_configure", "u", None),
+        ("xdg_toplevel", 0) => ("destroy", "", None),
+        ("xdg_toplevel", 2) => ("set_title", "s", None),
+        ("xdg_toplevel", 3) => ("set_app_id", "s", None),
+        ("xdg_toplevel", 7) => ("set_max_size", "ii", None),
+        ("xdg_toplevel", 8) => ("set_min_size", "ii", None),
+        ("zwlr_layer_shell_v1", 0) => {
+            ("get_layer_surface", "no?ous", Some("zwlr_layer_surface_v1"))
+        }
+        ("zwlr_layer_shell_v1", 1) => ("destroy", "", None),
+        ("zwlr_layer_surface_v1", 0) => ("set_size", "uu", None),
+        ("zwlr_layer_surface_v1", 1) => ("set_anchor", "u", None),
+        ("zwlr_layer_surface_v1", 2) => ("set_exclusive_zone", "i", None),
+        ("zwlr_layer_surface_v1", 3) => ("set_margin", "iiii", None),
+        ("zwlr_layer_surface_v1", 4) => ("set_keyboard_interactivity", "u", None),
+        ("zwlr_layer_surface_v1", 6) => ("ack_configure", "u", None),
+        ("zwlr_layer_surface_v1", 7) => ("destroy", "", None),
+        ("zwlr_layer_surface_v1", 8) => ("set_layer", "u", None),
+        _ => return None,
+    })
+}
+
+fn has_xkb() -> bool {
+    xkbcommon_dl::xkbcommon_option().is_some()
+}
+
+/// The default keymap for the environment, as xkb text
+fn keymap() -> String {
+    let xkb = xkbcommon_dl::xkbcommon_option().expect("keyboard tests need libxkbcommon");
+    let null = std::ptr::null();
+    let names = xkb_rule_names {
+        rules: null,
+        model: null,
+        layout: null,
+        variant: null,
+        options: null,
+    };
+    unsafe {
+        let context = (xkb.xkb_context_new)(xkb_context_flags::XKB_CONTEXT_NO_FLAGS);
+        let keymap = (xkb.xkb_keymap_new_from_names)(
+            context,
+            &names,
+            xkb_keymap_compile_flags::XKB_KEYMAP_COMPILE_NO_FLAGS,
+        );
+        assert!(!keymap.is_null(), "failed to compile the default keymap");
+        let text =
+            (xkb.xkb_keymap_get_as_string)(keymap, xkb_keymap_format::XKB_KEYMAP_FORMAT_TEXT_V1);
+        let string = CStr::from_ptr(text).to_string_lossy().into_owned();
+        libc::free(text as *mut _);
+        (xkb.xkb_keymap_unref)(keymap);
+        (xkb.xkb_context_unref)(context);
+        string
+    }
+}
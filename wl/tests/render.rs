@@ -0,0 +1,287 @@
+//! Snapshot tests through `--render-to`, no compositor needed
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+const COLORS: [&str; 8] = [
+    "-nb", "#202020", "-hb", "#404040", "-sb", "#808080", "-nf", "#00ff00",
+];
+
+fn run(name: &str, args: &[&str], input: &str) -> (PathBuf, Output) {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_wl"))
+        .args(args)
+        .arg("--render-to")
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("failed to run wl");
+    // wl may well have exited already, on a bad argument
+    let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+    (path, child.wait_with_output().unwrap())
+}
+
+struct Ppm {
+    width: usize,
+    height: usize,
+    pixels: Vec<[u8; 3]>,
+}
+
+impl Ppm {
+    fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        self.pixels[x + y * self.width]
+    }
+}
+
+fn render(name: &str, args: &[&str], input: &str) -> Ppm {
+    let (path, output) = run(&format!("{}.ppm", name), args, input);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let bytes = std::fs::read(path).unwrap();
+    let header: Vec<_> = bytes.splitn(4, |&b| b == b'\n').collect();
+    assert_eq!(header[0], b"P6");
+    assert_eq!(header[2], b"255");
+    let size = std::str::from_utf8(header[1]).unwrap();
+    let (width, height) = size.split_once(' ').unwrap();
+    let (width, height) = (width.parse().unwrap(), height.parse().unwrap());
+    let pixels: Vec<_> = header[3].chunks(3).map(|p| [p[0], p[1], p[2]]).collect();
+    assert_eq!(pixels.len(), width * height);
+    Ppm {
+        width,
+        height,
+        pixels,
+    }
+}
+
+#[test]
+fn size_follows_layout() {
+    let args = ["-b", "1", "-w", "100", "-h", "50"];
+    let img = render("horizontal", &args, "one\ntwo\nthree\n");
+    // a 40px query bar, then a row of three 100x50 buttons, all within 1px borders
+    assert_eq!((img.width, img.height), (1 + 3 * 101, 1 + 41 + 51));
+
+    let args = ["-b", "1", "-w", "100", "-h", "50", "-l", "vertical"];
+    let img = render("vertical", &args, "one\ntwo\nthree\n");
+    assert_eq!((img.width, img.height), (1 + 101, 1 + 41 + 3 * 51));
+
+    let args = ["-b", "1", "-w", "100", "-h", "50", "-l", "grid:2"];
+    let img = render("grid", &args, "one\ntwo\nthree\n");
+    assert_eq!((img.width, img.height), (1 + 2 * 101, 1 + 41 + 2 * 51));
+    // more columns than buttons leaves none empty
+    let args = ["-b", "3", "-w", "100", "-h", "50", "-l", "grid:50"];
+    let img = render("wide_grid", &args, "one\ntwo\nthree\n");
+    assert_eq!((img.width, img.height), (3 + 3 * 103, 3 + 43 + 53));
+}
+
+#[test]
+fn auto_and_fit_widths_follow_labels() {
+    let input = "i\nwwwwwwww\n";
+    // one button as wide as the widest label, and its 1px borders
+    let args = ["-b", "1", "-w", "auto", "-h", "50"];
+    let one = render("auto_one", &args, "wwwwwwww\n").width;
+    assert!(one > 1 + 2 * 10, "{}", one);
+    let img = render("auto", &args, input);
+    assert_eq!((img.width, img.height), (1 + 2 * (one - 1), 1 + 41 + 51));
+
+    // fit gives the narrow label a narrower button
+    let args = ["-b", "1", "-w", "fit", "-h", "50"];
+    let img = render("fit", &args, input);
+    assert!(
+        one < img.width && img.width < 1 + 2 * (one - 1),
+        "{}",
+        img.width
+    );
+    assert_eq!(img.height, 1 + 41 + 51);
+    // unless the buttons share a column
+    let args = ["-b", "1", "-w", "fit", "-h", "50", "-l", "vertical"];
+    let img = render("fit_vertical", &args, input);
+    assert_eq!((img.width, img.height), (one, 1 + 41 + 2 * 51));
+}
+
+#[test]
+fn buttons_drawn_in_state_colors() {
+    let mut args = vec!["-b", "1", "-w", "100", "-h", "50"];
+    args.extend(&COLORS);
+    args.extend(&["--pointer", "150,60", "--focus", "2"]);
+    let img = render("states", &args, "one\ntwo\nthree\n");
+
+    // just inside the top left corner of each button, clear of its label
+    assert_eq!(img.pixel(3, 44), [0x20, 0x20, 0x20]);
+    assert_eq!(img.pixel(104, 44), [0x40, 0x40, 0x40]);
+    assert_eq!(img.pixel(205, 44), [0x80, 0x80, 0x80]);
+}
+
+#[test]
+fn labels_drawn_within_their_buttons() {
+    let mut args = vec!["-b", "1", "-w", "100", "-h", "50"];
+    args.extend(&COLORS);
+    let img = render("labels", &args, "one\ntwo\n");
+
+    let green = |x: usize, y: usize| img.pixel(x, y) == [0, 0xff, 0];
+    let in_button = |x0: usize| {
+        (x0..x0 + 100)
+            .flat_map(|x| (42..92).map(move |y| (x, y)))
+            .filter(|&(x, y)| green(x, y))
+            .count()
+    };
+    assert!(in_button(1) > 0);
+    assert!(in_button(102) > 0);
+    // nothing typed, so nothing in the query bar
+    assert!((0..img.width).all(|x| (0..42).all(|y| !green(x, y))));
+}
+
+#[test]
+fn writes_png() {
+    let (path, output) = run("menu.png", &["-w", "100", "-h", "50"], "one\ntwo\n");
+    assert!(output.status.success());
+    let bytes = std::fs::read(path).unwrap();
+    assert_eq!(&bytes[..8], b"\x89PNG\r\n\x1a\n");
+    assert_eq!(&bytes[12..16], b"IHDR");
+    assert_eq!(&bytes[16..24], &[0, 0, 0, 203, 0, 0, 0, 93]);
+    assert_eq!(&bytes[bytes.len() - 8..bytes.len() - 4], b"IEND");
+}
+
+#[test]
+fn rejects_focus_past_the_last_button() {
+    let (_, output) = run("focus.ppm", &["--focus", "2"], "one\ntwo\n");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn rejects_font_sizes_that_draw_nothing() {
+    for size in ["-10", "0", "NaN", "inf"] {
+        let (_, output) = run("font_size.ppm", &["-fs", size], "one\n");
+        assert_eq!(output.status.code(), Some(4), "{}", size);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("-fs must be"), "{}", stderr);
+    }
+}
+
+#[test]
+fn reports_missing_font_files() {
+    let (_, output) = run("font.ppm", &["-f", "/nonexistent"], "one\n");
+    assert_eq!(output.status.code(), Some(4));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("/nonexistent"), "{}", stderr);
+    assert!(stderr.contains("No such file"), "{}", stderr);
+}
+
+#[test]
+fn rejects_scales_that_draw_nothing() {
+    for scale in ["-1", "0", "NaN", "inf"] {
+        let (_, output) = run("scale.ppm", &["--scale", scale], "one\n");
+        assert_eq!(output.status.code(), Some(4), "{}", scale);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("--scale must be"), "{}", stderr);
+    }
+}
+
+#[test]
+fn rejects_absurdly_big_buffers() {
+    for args in [["--size", "100000x100000"], ["--scale", "1e30"]] {
+        let (_, output) = run("huge.ppm", &args, "one\n");
+        assert_eq!(output.status.code(), Some(4), "{:?}", args);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("too big to draw"), "{}", stderr);
+    }
+}
+
+#[test]
+fn scale_multiplies_buffer_pixels() {
+    let mut args = vec!["-b", "1", "-w", "100", "-h", "50", "--scale", "2"];
+    args.extend(&COLORS);
+    // the pointer is in logical pixels, over the second button
+    args.extend(&["--pointer", "150,60"]);
+    let img = render("scaled", &args, "one\ntwo\n");
+    assert_eq!(
+        (img.width, img.height),
+        (2 * (1 + 2 * 101), 2 * (1 + 41 + 51))
+    );
+    assert_eq!(img.pixel(2 * 3, 2 * 44), [0x20, 0x20, 0x20]);
+    assert_eq!(img.pixel(2 * 104, 2 * 44), [0x40, 0x40, 0x40]);
+
+    let args = ["-w", "100", "-h", "50", "--scale", "1.5"];
+    let img = render("fractional", &args, "one\ntwo\n");
+    assert_eq!((img.width, img.height), (305, 140));
+}
+
+#[test]
+fn configured_size_stretches_buttons() {
+    let mut args = vec!["-b", "1", "-w", "100", "-h", "50", "--size", "601x0"];
+    args.extend(&COLORS);
+    args.extend(&["--focus", "2"]);
+    let img = render("stretched", &args, "one\ntwo\nthree\n");
+    // the height is left to the menu
+    assert_eq!((img.width, img.height), (601, 1 + 41 + 51));
+    // three 199px buttons and four borders
+    assert_eq!(img.pixel(1 + 198, 44), [0x20, 0x20, 0x20]);
+    assert_eq!(img.pixel(1 + 199 + 1 + 199 + 1, 44), [0x80, 0x80, 0x80]);
+    assert_eq!(img.pixel(599, 44), [0x80, 0x80, 0x80]);
+}
+
+#[test]
+fn configured_size_squeezes_buttons() {
+    let args = ["-b", "1", "-w", "100", "-h", "50", "--size", "90x60"];
+    let img = render("squeezed", &args, "one\ntwo\nthree\n");
+    assert_eq!((img.width, img.height), (90, 60));
+}
+
+#[test]
+fn delimited_input_draws_only_labels() {
+    let mut args = vec!["-b", "1", "-w", "100", "-h", "50"];
+    args.extend(&COLORS);
+    let plain = render("plain", &args, "one\ntwo\n");
+    args.extend(&["--delimiter", "\\t"]);
+    let delimited = render("delimited", &args, "one\tuno\ntwo\tdos\n");
+    assert!(plain.pixels == delimited.pixels);
+}
+
+#[test]
+fn json_options_draw_their_colors_and_tooltips() {
+    let mut args = vec!["-b", "1", "-w", "100", "-h", "50", "--json"];
+    args.extend(&COLORS);
+    let input = r##"[
+        {"label": "one", "color": "#ff0000", "tooltip": "the first"},
+        {"label": "two", "disabled": true}
+    ]"##;
+    let img = render("json", &args, input);
+    let count = |img: &Ppm, x0: usize, y0: usize, h: usize, color: [u8; 3]| {
+        (x0..x0 + 100)
+            .flat_map(|x| (y0..y0 + h).map(move |y| (x, y)))
+            .filter(|&(x, y)| img.pixel(x, y) == color)
+            .count()
+    };
+    let (red, green) = ([0xff, 0, 0], [0, 0xff, 0]);
+    assert!(count(&img, 1, 42, 50, red) > 0);
+    assert_eq!(count(&img, 1, 42, 50, green), 0);
+    // disabled, so drawn faded: never at full -nf
+    assert_eq!(count(&img, 102, 42, 50, green), 0);
+    assert!(count(&img, 102, 42, 50, [0x20, 0x20, 0x20]) < 100 * 50);
+    assert_eq!(count(&img, 1, 1, 40, [0x20, 0x20, 0x20]), 100 * 40);
+
+    // hovered, the button shows -hf and the bar its tooltip
+    args.extend(&["-hf", "#0000ff", "--pointer", "50,60"]);
+    let img = render("json_hover", &args, input);
+    assert!(count(&img, 1, 42, 50, [0, 0, 0xff]) > 0);
+    assert!(count(&img, 1, 1, 40, [0x20, 0x20, 0x20]) < 100 * 40);
+    assert_eq!(count(&img, 1, 1, 40, green), 0);
+}
+
+#[test]
+fn squeezed_narrower_than_the_borders() {
+    for size in ["1x5", "3x1", "1x1"] {
+        let args = ["-b", "2", "-w", "100", "-h", "50", "--size", size];
+        let img = render(&format!("narrow{}", size), &args, "one\ntwo\n");
+        let (width, height) = size.split_once('x').unwrap();
+        assert_eq!(
+            (img.width, img.height),
+            (width.parse().unwrap(), height.parse().unwrap())
+        );
+    }
+}
@@ -0,0 +1,464 @@
+//! End-to-end tests against the fake compositor in `mock`
+
+mod mock;
+
+use mock::{Arg, Mock, GLOBALS, KEY_B, KEY_E, KEY_ENTER, KEY_ESC, KEY_R, KEY_TAB};
+
+const BUTTONS: [&str; 6] = ["-b", "1", "-w", "100", "-h", "50"];
+
+fn stdout(output: &std::process::Output) -> &str {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    std::str::from_utf8(&output.stdout).unwrap()
+}
+
+#[test]
+fn click_prints_the_button_clicked() {
+    let mut mock = Mock::spawn(&BUTTONS, "a\nb\nc\n");
+    mock.configure(0, 0);
+    mock.next_frame();
+    mock.pointer_enter(150.0, 60.0);
+    mock.click();
+    assert_eq!(stdout(&mock.finish()), "b\n");
+}
+
+#[test]
+fn prints_the_value_of_a_delimited_line() {
+    let mut args = BUTTONS.to_vec();
+    args.extend(&["--delimiter", ": "]);
+    let input = "Shut down: systemctl poweroff\nReboot: systemctl reboot\nno value\n";
+    let mut mock = Mock::spawn(&args, input);
+    mock.configure(0, 0);
+    mock.next_frame();
+    mock.pointer_enter(50.0, 60.0);
+    mock.click();
+    assert_eq!(stdout(&mock.finish()), "systemctl poweroff\n");
+
+    // labels are what typing matches
+    let mut mock = Mock::spawn(&args, input);
+    mock.configure(0, 0);
+    mock.next_frame();
+    for key in [KEY_R, KEY_E, KEY_ENTER] {
+        mock.key(key);
+    }
+    assert_eq!(stdout(&mock.finish()), "systemctl reboot\n");
+}
+
+#[test]
+fn fit_buttons_are_clicked_where_they_are_drawn() {
+    let args = ["-b", "1", "-w", "fit", "-h", "50"];
+    let mut mock = Mock::spawn(&args, "i\nwwwwwwww\n");
+    mock.configure(0, 0);
+    let width = mock.next_frame().width as f64;
+    // left of the middle, which with equal widths would be the first button
+    mock.pointer_enter(0.4 * width, 60.0);
+    mock.click();
+    assert_eq!(stdout(&mock.finish()), "wwwwwwww\n");
+}
+
+#[test]
+fn shows_a_layer_surface_sized_to_the_menu() {
+    let mut mock = Mock::spawn(&BUTTONS, "a\nb\nc\n");
+    mock.configure(0, 0);
+    let frame = mock.next_frame();
+    assert_eq!((frame.width, frame.height), (1 + 3 * 101, 1 + 41 + 51));
+
+    let layer = mock.called("zwlr_layer_shell_v1.get_layer_surface");
+    assert_eq!(layer.len(), 1);
+    assert_eq!(layer[0][4], Arg::Str("wtmenu".into()));
+    let size = mock.called("zwlr_layer_surface_v1.set_size");
+    assert_eq!(size, [&[Arg::Uint(304), Arg::Uint(93)][..]]);
+    assert_eq!(mock.called("zwlr_layer_surface_v1.ack_configure").len(), 1);
+}
+
+#[test]
+fn places_the_layer_surface_as_asked() {
+    let mut args = BUTTONS.to_vec();
+    args.extend(&["--anchor", "top,left,right", "--margin", "1,2,3,4"]);
+    args.extend(&[
+        "--layer",
+        "overlay",
+        "--exclusive-zone",
+        "7",
+        "--namespace",
+        "menu",
+    ]);
+    let mut mock = Mock::spawn(&args, "a\nb\nc\n");
+    mock.configure(600, 93);
+    let frame = mock.next_frame();
+    assert_eq!((frame.width, frame.height), (600, 93));
+
+    let layer = mock.called("zwlr_layer_shell_v1.get_layer_surface");
+    assert_eq!(layer[0][3..], [Arg::Uint(3), Arg::Str("menu".into())]);
+    // top 1, left 4, right 8
+    let anchor = mock.called("zwlr_layer_surface_v1.set_anchor");
+    assert_eq!(anchor, [&[Arg::Uint(1 | 4 | 8)][..]]);
+    let margin = mock.called("zwlr_layer_surface_v1.set_margin");
+    assert_eq!(margin[0], [1, 2, 3, 4].map(Arg::Int));
+    let zone = mock.called("zwlr_layer_surface_v1.set_exclusive_zone");
+    assert_eq!(zone, [&[Arg::Int(7)][..]]);
+    // stretched between the left and right edges, so only the height is ours
+    let size = mock.called("zwlr_layer_surface_v1.set_size");
+    assert_eq!(size, [&[Arg::Uint(0), Arg::Uint(93)][..]]);
+
+    let mut args = BUTTONS.to_vec();
+    args.extend(&["--anchor", "left-top-bottom"]);
+    let mut mock = Mock::spawn(&args, "a\nb\nc\n");
+    mock.configure(304, 500);
+    mock.next_frame();
+    let size = mock.called("zwlr_layer_surface_v1.set_size");
+    assert_eq!(size, [&[Arg::Uint(304), Arg::Uint(0)][..]]);
+}
+
+#[test]
+fn rejects_bad_placements() {
+    let placements = [
+        ["--anchor", "middle"],
+        ["--anchor", "top-"],
+        ["--margin", "1,2,3"],
+        ["--margin", "1px"],
+        ["--layer", "up"],
+    ];
+    for placement in placements {
+        let output = Mock::spawn(&placement, "a\n").finish();
+        assert_eq!(output.status.code(), Some(4), "{:?}", placement);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(&placement[0][2..]), "{}", stderr);
+    }
+}
+
+#[test]
+fn fills_the_configured_width() {
+    let mut mock = Mock::spawn(&BUTTONS, "a\nb\nc\n");
+    mock.configure(600, 0);
+    let frame = mock.next_frame();
+    assert_eq!((frame.width, frame.height), (600, 93));
+}
+
+#[test]
+fn survives_a_size_narrower_than_the_borders() {
+    let mut mock = Mock::spawn(&["-b", "2"], "one\n");
+    mock.configure(1, 5);
+    let frame = mock.next_frame();
+    assert_eq!((frame.width, frame.height), (1, 5));
+    mock.close();
+    assert_eq!(mock.finish().status.code(), Some(2));
+}
+
+#[test]
+fn each_output_gets_its_own_width() {
+    let mut globals = GLOBALS.to_vec();
+    globals.extend([("wl_output", 3), ("wl_output", 3)]);
+    let mut args = BUTTONS.to_vec();
+    args.extend(&["--output", "all"]);
+    let mut mock = Mock::spawn_with(&globals, &args, "a\nb\nc\n");
+    mock.configure_each(&[(601, 0), (301, 0)]);
+    let surfaces = mock.objects("wl_surface");
+    let drawn = |m: &Mock, s| {
+        m.frames
+            .iter()
+            .rev()
+            .find(|f| f.surface == s)
+            .map(|f| f.width)
+    };
+    mock.dispatch_until(|m| surfaces.iter().all(|&s| drawn(m, s).is_some()));
+    assert_eq!(drawn(&mock, surfaces[0]), Some(601));
+    assert_eq!(drawn(&mock, surfaces[1]), Some(301));
+
+    // buttons are 100px wide on the second output, but would be 200px on the first
+    mock.pointer_enter_on(surfaces[1], 250.0, 60.0);
+    mock.click();
+    assert_eq!(stdout(&mock.finish()), "c\n");
+}
+
+#[test]
+fn hover_redraws() {
+    let mut args = BUTTONS.to_vec();
+    args.extend(&["-nb", "#202020", "-hb", "#404040"]);
+    let mut mock = Mock::spawn(&args, "a\nb\n");
+    mock.configure(0, 0);
+    assert_eq!(mock.next_frame().pixel(104, 44), [0x20, 0x20, 0x20]);
+    mock.pointer_enter(150.0, 60.0);
+    assert_eq!(mock.next_frame().pixel(104, 44), [0x40, 0x40, 0x40]);
+    mock.pointer_motion(50.0, 60.0);
+    let frame = mock.next_frame();
+    assert_eq!(frame.pixel(3, 44), [0x40, 0x40, 0x40]);
+    assert_eq!(frame.pixel(104, 44), [0x20, 0x20, 0x20]);
+}
+
+#[test]
+fn touch_picks_where_it_lifts() {
+    let args = [
+        "-b", "1", "-w", "100", "-h", "50", "-nb", "#202020", "-sb", "#808080",
+    ];
+    let mut mock = Mock::spawn(&args, "a\nb\nc\n");
+    mock.configure(0, 0);
+    mock.next_frame();
+    mock.touch_down(0, 150.0, 60.0);
+    assert_eq!(mock.next_frame().pixel(104, 44), [0x80, 0x80, 0x80]);
+    // sliding off and back onto the button it started on
+    mock.touch_motion(0, 250.0, 60.0);
+    assert_eq!(mock.next_frame().pixel(104, 44), [0x20, 0x20, 0x20]);
+    mock.touch_motion(0, 160.0, 60.0);
+    mock.touch_up(0);
+    assert_eq!(stdout(&mock.finish()), "b\n");
+}
+
+#[test]
+fn two_touch_points_pick_nothing() {
+    let mut mock = Mock::spawn(&BUTTONS, "a\nb\nc\n");
+    mock.configure(0, 0);
+    mock.next_frame();
+    mock.touch_down(0, 50.0, 60.0);
+    mock.touch_down(1, 150.0, 60.0);
+    mock.touch_up(0);
+    mock.touch_up(1);
+    // with neither lift picking, the menu only ends when it is closed
+    mock.close();
+    let output = mock.finish();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn follows_seats_and_their_capabilities() {
+    let mut mock = Mock::spawn(&BUTTONS, "a\nb\nc\n");
+    mock.configure(0, 0);
+    mock.next_frame();
+    // ids are reused once released, so count requests rather than objects
+    let got = |m: &Mock, device: &str| m.called(&format!("wl_seat.get_{}", device)).len();
+    for device in ["pointer", "keyboard", "touch"] {
+        assert_eq!(got(&mock, device), 1, "{}", device);
+    }
+
+    // the keyboard is unplugged, pointer 1 and touch 4 stay
+    let seat = mock.object("wl_seat").unwrap();
+    mock.set_capabilities(seat, 1 | 4);
+    assert!(mock.dispatch_until(|m| m.called("wl_keyboard.release").len() == 1));
+
+    // a second seat gets devices of its own
+    let name = mock.add_global("wl_seat", 5);
+    assert!(mock.dispatch_until(|m| got(m, "touch") == 2));
+    assert_eq!((got(&mock, "pointer"), got(&mock, "keyboard")), (2, 2));
+
+    // and releases them with the seat when it goes
+    mock.remove_global(name);
+    assert!(mock.dispatch_until(|m| m.called("wl_seat.release").len() == 1));
+    for device in ["wl_pointer", "wl_keyboard", "wl_touch"] {
+        let released = mock.called(&format!("{}.release", device)).len();
+        let expected = if device == "wl_keyboard" { 2 } else { 1 };
+        assert_eq!(released, expected, "{}", device);
+    }
+
+    // the first seat's pointer still picks
+    mock.pointer_enter(150.0, 60.0);
+    mock.click();
+    assert_eq!(stdout(&mock.finish()), "b\n");
+}
+
+#[test]
+fn typing_filters_and_enter_picks() {
+    let mut mock = Mock::spawn(&BUTTONS, "a\nb\nc\n");
+    mock.configure(0, 0);
+    mock.next_frame();
+    mock.key(KEY_B);
+    mock.key(KEY_ENTER);
+    assert_eq!(stdout(&mock.finish()), "b\n");
+}
+
+#[test]
+fn tab_moves_focus() {
+    let mut mock = Mock::spawn(&BUTTONS, "a\nb\nc\n");
+    mock.configure(0, 0);
+    mock.next_frame();
+    mock.key(KEY_TAB);
+    mock.key(KEY_TAB);
+    mock.key(KEY_ENTER);
+    assert_eq!(stdout(&mock.finish()), "b\n");
+}
+
+#[test]
+fn escape_picks_nothing() {
+    let mut mock = Mock::spawn(&BUTTONS, "a\nb\nc\n");
+    mock.configure(0, 0);
+    mock.next_frame();
+    mock.key(KEY_ESC);
+    let output = mock.finish();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn exit_status_tells_how_the_menu_ended() {
+    let mut mock = Mock::spawn(&BUTTONS, "a\nb\n");
+    mock.configure(0, 0);
+    mock.next_frame();
+    mock.close();
+    assert_eq!(mock.finish().status.code(), Some(2));
+
+    let without_layer_shell: Vec<_> = (GLOBALS.iter())
+        .filter(|(global, _)| *global != "zwlr_layer_shell_v1")
+        .copied()
+        .collect();
+    let mut mock = Mock::spawn_with(&without_layer_shell, &BUTTONS, "a\nb\n");
+    mock.configure(0, 0);
+    mock.next_frame();
+    mock.close();
+    assert_eq!(mock.finish().status.code(), Some(1));
+
+    let output = Mock::spawn(&BUTTONS, "").finish();
+    assert_eq!(output.status.code(), Some(3));
+    let output = Mock::spawn(&["-b", "-1"], "a\n").finish();
+    assert_eq!(output.status.code(), Some(4));
+}
+
+/// Click the `button`th of three, formatting the pick with `format`
+fn pick(format: &str, button: usize, input: &str) -> String {
+    let mut args = BUTTONS.to_vec();
+    args.extend(&["--delimiter", "=", "--format", format]);
+    let mut mock = Mock::spawn(&args, input);
+    mock.configure(0, 0);
+    mock.next_frame();
+    mock.pointer_enter(50.0 + 101.0 * button as f64, 60.0);
+    mock.click();
+    stdout(&mock.finish()).to_owned()
+}
+
+#[test]
+fn formats_the_pick() {
+    let input = "a=1\nb=2\nc=3\n";
+    assert_eq!(pick("value", 1, input), "2\n");
+    assert_eq!(pick("line", 1, input), "b=2\n");
+    assert_eq!(pick("index", 1, input), "1\n");
+    assert_eq!(
+        pick("{label} is {value}, {{{index}}}", 2, input),
+        "c is 3, {2}\n"
+    );
+}
+
+#[test]
+fn formats_the_pick_as_json() {
+    let json = pick("json", 0, "say \"hi\"\t\\=x\nb\nc\n");
+    let expected = r#"{"index":0,"label":"say \"hi\"\t\\","value":"x","button":0}"#;
+    assert_eq!(json, format!("{}\n", expected));
+
+    // typing reorders the buttons, but the index stays that of the input
+    let mut args = BUTTONS.to_vec();
+    args.extend(&["--format", "json"]);
+    let mut mock = Mock::spawn(&args, "a\nb\nc\n");
+    mock.configure(0, 0);
+    mock.next_frame();
+    mock.key(KEY_B);
+    mock.key(KEY_ENTER);
+    let expected = r#"{"index":1,"label":"b","value":"b","button":0}"#;
+    assert_eq!(stdout(&mock.finish()), format!("{}\n", expected));
+}
+
+#[test]
+fn rejects_unknown_formats() {
+    for format in ["yaml", "{nope}", "{label", "label}"] {
+        let output = Mock::spawn(&["--format", format], "a\n").finish();
+        assert!(!output.status.success(), "{}", format);
+    }
+}
+
+#[test]
+fn falls_back_to_a_toplevel() {
+    let globals: Vec<_> = (GLOBALS.iter())
+        .filter(|(global, _)| *global != "zwlr_layer_shell_v1")
+        .copied()
+        .collect();
+    let mut mock = Mock::spawn_with(&globals, &BUTTONS, "a\nb\nc\n");
+    mock.configure(0, 0);
+    let frame = mock.next_frame();
+    assert_eq!((frame.width, frame.height), (304, 93));
+    assert_eq!(mock.called("xdg_surface.ack_configure").len(), 1);
+    mock.pointer_enter(250.0, 60.0);
+    mock.click();
+    assert_eq!(stdout(&mock.finish()), "c\n");
+}
+
+#[test]
+fn reports_every_missing_global() {
+    let globals: Vec<_> = (GLOBALS.iter())
+        .filter(|(global, _)| *global != "wl_shm" && *global != "wl_compositor")
+        .copied()
+        .collect();
+    let output = Mock::spawn_with(&globals, &BUTTONS, "a\n").finish();
+    assert_eq!(output.status.code(), Some(4));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("wl_compositor") && stderr.contains("wl_shm"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn reads_json_options() {
+    let mut args = BUTTONS.to_vec();
+    args.push("--json");
+    let input = r#"[{"label": "Lock", "value": "loginctl lock-session"}, {"label": "b"}]"#;
+    let mut mock = Mock::spawn(&args, input);
+    mock.configure(0, 0);
+    mock.next_frame();
+    mock.pointer_enter(50.0, 60.0);
+    mock.click();
+    assert_eq!(stdout(&mock.finish()), "loginctl lock-session\n");
+
+    // one object after another, with `line` being the object
+    args.extend(&["--format", "line"]);
+    let input = "{\"label\": \"a\"}\n{\"label\": \"b\", \"hotkey\": \"e\"}\n";
+    let mut mock = Mock::spawn(&args, input);
+    mock.configure(0, 0);
+    mock.next_frame();
+    mock.key(KEY_E);
+    assert_eq!(
+        stdout(&mock.finish()),
+        "{\"label\":\"b\",\"hotkey\":\"e\"}\n"
+    );
+}
+
+#[test]
+fn disabled_options_cannot_be_picked() {
+    let mut args = BUTTONS.to_vec();
+    args.push("--json");
+    let input = r#"[{"label": "a", "disabled": true}, {"label": "b"}]"#;
+    let mut mock = Mock::spawn(&args, input);
+    mock.configure(0, 0);
+    mock.next_frame();
+    mock.pointer_enter(50.0, 60.0);
+    mock.click();
+    // nothing focused, so enter would pick the top match
+    mock.key(KEY_ENTER);
+    mock.pointer_motion(150.0, 60.0);
+    mock.click();
+    assert_eq!(stdout(&mock.finish()), "b\n");
+}
+
+#[test]
+fn rejects_malformed_json() {
+    for (input, at) in [
+        (
+            "{\"label\": \"a\"}\n{\"label\": \"b\",}\n",
+            "line 2 column 15",
+        ),
+        (
+            "{\"label\": \"a\"}\n  {\"value\": \"b\"}\n",
+            "line 2 column 3",
+        ),
+        (
+            "[{\"label\": \"a\"}, {\"label\": \"b\", \"color\": \"red\"}]",
+            "line 1 column",
+        ),
+        ("[{\"label\": \"a\"}, 4]", "line 1 column"),
+    ] {
+        let output = Mock::spawn(&["--json"], input).finish();
+        assert_eq!(output.status.code(), Some(4), "{}", input);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(at), "{}", stderr);
+    }
+}